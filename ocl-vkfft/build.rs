@@ -1,22 +1,77 @@
 use std::path::PathBuf;
 
-fn main() {
-    // ./target/debug/build/ocl_vkfft-.../out/
-    let out_path = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+// Release of the vendored vkFFT/ headers, as returned by VkFFTGetVersion. Update it together
+// with them, after checking that the bindings still match.
+const VKFFT_VERSION: u32 = 10304;
 
+fn main() {
     println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo::rustc-link-search={}", out_path.to_str().unwrap());
-    println!("cargo:rustc-link-lib=static=foo");
-    println!("cargo:rerun-if-changed=src/test.c");
+    println!("cargo:rerun-if-changed=vkFFT/vkFFT.h");
+    check_version();
+
+    // The VkFFT entry points are static inline functions, called through non-inline wrappers.
+    #[cfg(feature = "bindgen")]
+    let wrappers = generate_bindings();
+    #[cfg(not(feature = "bindgen"))]
+    let wrappers = PathBuf::from("src/wrappers.c");
+    println!("cargo:rerun-if-changed={}", wrappers.display());
+
+    // Needs CL/cl.h in the include path of the C compiler, see Cargo.toml.
+    cc::Build::new()
+        .file(&wrappers)
+        .include("vkFFT")
+        .include(".")
+        .define("VKFFT_BACKEND", "3")
+        .define("CL_TARGET_OPENCL_VERSION", opencl_version())
+        .opt_level(3) // Change to 0 and add .debug(true) for debugging
+        .warnings(false)
+        .compile("vkfft_wrappers");
+}
+
+/// Fails the build if vkFFT/vkFFT.h is not the pinned release.
+fn check_version() {
+    let header = std::fs::read_to_string("vkFFT/vkFFT.h").expect("Couldn't read vkFFT/vkFFT.h");
+    let found = header
+        .split("VkFFTGetVersion()")
+        .nth(1)
+        .and_then(|body| body.split("return").nth(1))
+        .and_then(|ret| ret.split(';').next())
+        .and_then(|version| version.trim().parse::<u32>().ok())
+        .expect("No VkFFTGetVersion in vkFFT/vkFFT.h");
+    if found != VKFFT_VERSION {
+        panic!(
+            "vkFFT/vkFFT.h is VkFFT {}, ocl-vkfft is pinned to {} : update VKFFT_VERSION in \
+             build.rs after checking the bindings",
+            found, VKFFT_VERSION
+        );
+    }
+}
+
+/// Value of CL_TARGET_OPENCL_VERSION, the oldest OpenCL version selected by the features.
+fn opencl_version() -> &'static str {
+    if std::env::var_os("CARGO_FEATURE_OPENCL_1_2").is_some() {
+        "120"
+    } else if std::env::var_os("CARGO_FEATURE_OPENCL_2_0").is_some() {
+        "200"
+    } else {
+        "300"
+    }
+}
 
+/// Writes `bindings.rs` and the wrappers of the static functions to `OUT_DIR`. Copy both to
+/// `src/bindings.rs` and `src/wrappers.c` to update the shipped bindings.
+#[cfg(feature = "bindgen")]
+fn generate_bindings() -> PathBuf {
+    // ./target/debug/build/ocl_vkfft-.../out/
+    let out_path = PathBuf::from(std::env::var("OUT_DIR").unwrap());
     let bindings = bindgen::Builder::default()
         // The input header we would like to generate bindings for.
         .header("vkFFT/vkFFT.h")
         .clang_arg("-IvkFFT")
         .clang_arg("-DVKFFT_BACKEND=3")
-        .clang_arg("-DCL_TARGET_OPENCL_VERSION=300")
+        .clang_arg(format!("-DCL_TARGET_OPENCL_VERSION={}", opencl_version()))
         .wrap_static_fns(true)
-        .wrap_static_fns_path(out_path.join("foo.c"))
+        .wrap_static_fns_path(out_path.join("wrappers.c"))
         .derive_default(true)
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
         .allowlist_recursively(true)
@@ -41,7 +96,8 @@ fn main() {
         .allowlist_function("initializeVkFFT")
         .allowlist_function("VkFFTAppend")
         .allowlist_function("deleteVkFFT")
-        // Tell cargo to invalidate the built crate whenever any of the included header files changed.
+        .allowlist_function("getVkFFTErrorString")
+        .allowlist_function("VkFFTGetVersion")
         // Finish the builder and generate the bindings.
         .generate()
         // Unwrap the Result and panic on failure.
@@ -50,70 +106,5 @@ fn main() {
     bindings
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings!");
-
-    // Compile the generated wrappers into an object file.
-    let clang_output = std::process::Command::new("clang")
-        .arg("-IvkFFT")
-        .arg("-I.")
-        .arg("-O3") // Change to "-g" for debugging 
-        .arg("-DVKFFT_BACKEND=3")
-        .arg("-DCL_TARGET_OPENCL_VERSION=300")
-        .arg("-o")
-        .arg(out_path.join("foo.o"))
-        .arg("-c")
-        .arg(out_path.join("foo.c"))
-        .output()
-        .unwrap();
-        
-    if !clang_output.status.success() {
-        panic!(
-            "Could not compile object file:\n{}",
-            String::from_utf8_lossy(&clang_output.stderr)
-        );
-    }
-    let clang_output2 = std::process::Command::new("clang")
-        .arg("-IvkFFT")
-        .arg("-DVKFFT_BACKEND=3")
-        .arg("-DCL_TARGET_OPENCL_VERSION=300")
-        .arg("-o")
-        .arg(out_path.join("test.o"))
-        .arg("-c")
-        .arg("src/test.c")
-        .output()
-        .unwrap();
-        
-    if !clang_output2.status.success() {
-        panic!(
-            "Could not compile object file:\n{}",
-            String::from_utf8_lossy(&clang_output2.stderr)
-        );
-    }
-
-    
-    let ar_output = std::process::Command::new("ar")
-        .arg("rcs")
-        .arg(out_path.join("libfoo.a"))
-        .arg(out_path.join("foo.o"))
-        .arg(out_path.join("test.o"))
-        .output()
-        .unwrap();
-
-    if !ar_output.status.success() {
-        panic!(
-            "Could not emit library file:\n{}",
-            String::from_utf8_lossy(&ar_output.stderr)
-        );
-    }
-
-    //cc::Build::new()
-    //    .file("src/foo.c")
-    //    .include(Path::new("vkFFT"))
-    //    .flag("-DVKFFT_BACKEND=3")
-    //    .flag("-DCL_TARGET_OPENCL_VERSION=300")
-    //    .flag("-fkeep-inline-functions")
-    //    .flag("-lOpenCL")
-    //    .flag("-lm")
-    //    .static_flag(true)
-    //    .cargo_metadata(true)
-    //    .compile("foo");
+    out_path.join("wrappers.c")
 }
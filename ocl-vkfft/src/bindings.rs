@@ -1908,3 +1908,30 @@ impl Default for VkFFTApplication {
         }
     }
 }
+unsafe extern "C" {
+    #[link_name = "initializeVkFFT__extern"]
+    pub fn initializeVkFFT(
+        app: *mut VkFFTApplication,
+        inputLaunchConfiguration: VkFFTConfiguration,
+    ) -> VkFFTResult;
+}
+unsafe extern "C" {
+    #[link_name = "VkFFTAppend__extern"]
+    pub fn VkFFTAppend(
+        app: *mut VkFFTApplication,
+        inverse: ::std::os::raw::c_int,
+        launchParams: *mut VkFFTLaunchParams,
+    ) -> VkFFTResult;
+}
+unsafe extern "C" {
+    #[link_name = "deleteVkFFT__extern"]
+    pub fn deleteVkFFT(app: *mut VkFFTApplication);
+}
+unsafe extern "C" {
+    #[link_name = "getVkFFTErrorString__extern"]
+    pub fn getVkFFTErrorString(result: VkFFTResult) -> *const ::std::os::raw::c_char;
+}
+unsafe extern "C" {
+    #[link_name = "VkFFTGetVersion__extern"]
+    pub fn VkFFTGetVersion() -> ::std::os::raw::c_int;
+}
@@ -0,0 +1,122 @@
+//! FFT convolutions fused by VkFFT : forward FFT, multiplication by the kernel spectrum and
+//! inverse FFT in a single launch.
+
+use ocl::{Buffer, Context, Device, OclPrm, Queue};
+
+use crate::plan::{LaunchError, Plan, PlanBuilder, PlanError};
+
+/// Complex convolution of up to 3 dimensions, see `Convolution::builder`.
+#[derive(Clone, Debug)]
+pub struct ConvolutionBuilder {
+    plan: PlanBuilder,
+    features: usize,
+    matrix: bool,
+    symmetric: bool,
+}
+
+impl ConvolutionBuilder {
+    /// Convolutions of `count` batches of fields by the same kernel.
+    pub fn batches(mut self, count: usize) -> ConvolutionBuilder {
+        self.plan = self.plan.batches(count);
+        self
+    }
+
+    /// Components of the convolved fields, stored one after the other.
+    /// Each component is convolved by its own kernel unless `matrix` is set.
+    pub fn features(mut self, count: usize) -> ConvolutionBuilder {
+        self.features = count;
+        self
+    }
+
+    /// Multiplies the vector of `features` components by a `features` × `features` kernel
+    /// matrix at each frequency, e.g. for Biot–Savart type operators.
+    pub fn matrix(mut self, matrix: bool) -> ConvolutionBuilder {
+        self.matrix = matrix;
+        self
+    }
+
+    /// The kernel matrix is symmetric and only its upper triangle is given, row by row.
+    pub fn symmetric(mut self, symmetric: bool) -> ConvolutionBuilder {
+        self.symmetric = symmetric;
+        self
+    }
+
+    /// Components of the kernel : one per feature, a full matrix or its upper triangle.
+    pub fn kernel_features(&self) -> usize {
+        match (self.matrix, self.symmetric) {
+            (false, _) => self.features,
+            (true, false) => self.features * self.features,
+            (true, true) => self.features * (self.features + 1) / 2,
+        }
+    }
+
+    /// Transforms `kernel`, given in physical space, in place into the layout read by the
+    /// convolution, then builds the convolution with it. The kernel holds `kernel_features()`
+    /// arrays of the plan size, one after the other.
+    pub fn build<T: OclPrm>(
+        self,
+        context: &Context,
+        device: Device,
+        queue: &Queue,
+        kernel: &Buffer<T>,
+    ) -> Result<Convolution<T>, PlanError> {
+        let kernel_features = self.kernel_features();
+        // The kernel is never batched.
+        let mut kernel_plan = self.plan.clone().batches(1).build_with(
+            context,
+            device,
+            kernel_features,
+            None,
+            |config| config.kernelConvolution = 1,
+        )?;
+        kernel_plan.forward(queue, kernel, kernel)?;
+        queue.finish().map_err(PlanError::Device)?;
+
+        let kernel_bytes = (std::mem::size_of::<T>() * kernel.len()) as u64;
+        let (features, matrix, symmetric) = (self.features, self.matrix, self.symmetric);
+        let plan = self.plan.build_with(
+            context,
+            device,
+            features,
+            Some((kernel.as_ptr(), kernel_bytes)),
+            |config| {
+                config.performConvolution = 1;
+                config.matrixConvolution = if matrix { features as u64 } else { 1 };
+                config.symmetricKernel = symmetric as u64;
+            },
+        )?;
+        Ok(Convolution {
+            plan,
+            kernel: kernel.clone(),
+        })
+    }
+}
+
+/// A convolution by a fixed kernel, whose spectrum stays on the device.
+pub struct Convolution<T: OclPrm> {
+    plan: Plan,
+    kernel: Buffer<T>,
+}
+
+impl<T: OclPrm> Convolution<T> {
+    /// A convolution of the given dimensions, of one scalar field by one kernel.
+    pub fn builder(size: &[usize]) -> ConvolutionBuilder {
+        ConvolutionBuilder {
+            plan: Plan::builder(size),
+            features: 1,
+            matrix: false,
+            symmetric: false,
+        }
+    }
+
+    /// Enqueues the convolution of `buffer` in place.
+    pub fn apply(&mut self, queue: &Queue, buffer: &Buffer<T>) -> Result<(), LaunchError> {
+        self.plan
+            .append_with_kernel(queue, -1, buffer, buffer, Some(&self.kernel))
+    }
+
+    /// The kernel, transformed into the layout of the convolution.
+    pub fn kernel(&self) -> &Buffer<T> {
+        &self.kernel
+    }
+}
@@ -0,0 +1,180 @@
+//! What VkFFT generated for a plan, for debugging performance.
+
+use std::ffi::CStr;
+use std::fmt;
+
+use crate::{VkFFTApplication, VkFFTAxis, VkFFTPlan};
+
+/// One kernel of the transform along an axis. Long axes are split into several uploads.
+#[derive(Clone, Debug)]
+pub struct Upload {
+    /// Length of the sequence transformed by this kernel.
+    pub size: u64,
+    /// Radix of each stage of the kernel.
+    pub radices: Vec<u32>,
+    pub name: String,
+    /// Buffers read and written by the last launch in this direction : `input`, `buffer`, `temp`
+    /// or `output`, as printed by VkFFT's `printMemoryLayout`.
+    pub reads: Option<&'static str>,
+    pub writes: Option<&'static str>,
+    /// Generated OpenCL source, kept when the plan is built with `keep_shader_code`.
+    pub source: Option<String>,
+}
+
+/// The kernels of the transform along one axis, in launch order.
+#[derive(Clone, Debug)]
+pub struct AxisReport {
+    pub axis: usize,
+    /// Whether the length goes through Bluestein's algorithm, with its own padded buffers.
+    pub bluestein: bool,
+    pub uploads: Vec<Upload>,
+}
+
+/// Kernels of both directions and the temporary buffer of a plan, see `Plan::report`.
+#[derive(Clone, Debug)]
+pub struct PlanReport {
+    pub forward: Vec<AxisReport>,
+    pub inverse: Vec<AxisReport>,
+    /// Bytes of the buffer VkFFT allocated to reorder multi-upload transforms, if any.
+    pub temp_buffer: Option<u64>,
+}
+
+impl PlanReport {
+    /// Reads the plans of `app`. Memory layouts are only known for `last_direction`, the direction
+    /// of the last launch, since VkFFT binds the buffers at launch time.
+    ///
+    /// # Safety
+    /// `app` must be an initialized application.
+    pub(crate) unsafe fn new(app: &VkFFTApplication, last_direction: Option<i32>) -> PlanReport {
+        let temp_buffer = if app.configuration.allocateTempBuffer != 0
+            && !app.configuration.tempBufferSize.is_null()
+        {
+            Some(*app.configuration.tempBufferSize)
+        } else {
+            None
+        };
+        PlanReport {
+            forward: axes(app, app.localFFTPlan, last_direction == Some(-1)),
+            inverse: axes(app, app.localFFTPlan_inverse, last_direction == Some(1)),
+            temp_buffer,
+        }
+    }
+
+    /// Sources of all the kernels, each preceded by a comment naming it.
+    pub fn sources(&self) -> String {
+        let mut s = String::new();
+        for (direction, axes) in [("forward", &self.forward), ("inverse", &self.inverse)] {
+            for axis in axes {
+                for upload in &axis.uploads {
+                    if let Some(source) = &upload.source {
+                        s.push_str(&format!(
+                            "// {} axis {} : {}\n{}\n",
+                            direction, axis.axis, upload.name, source
+                        ));
+                    }
+                }
+            }
+        }
+        s
+    }
+}
+
+unsafe fn axes(app: &VkFFTApplication, plan: *const VkFFTPlan, launched: bool) -> Vec<AxisReport> {
+    let Some(plan) = plan.as_ref() else {
+        return Vec::new();
+    };
+    (0..app.configuration.FFTdim as usize)
+        .filter(|a| plan.numAxisUploads[*a] > 0)
+        .map(|a| AxisReport {
+            axis: a,
+            bluestein: app.useBluesteinFFT[a] != 0,
+            uploads: (0..plan.numAxisUploads[a] as usize)
+                .map(|u| upload(app, &plan.axes[a][u], plan.axisSplit[a][u], launched))
+                .collect(),
+        })
+        .collect()
+}
+
+unsafe fn upload(app: &VkFFTApplication, axis: &VkFFTAxis, size: u64, launched: bool) -> Upload {
+    let constants = &axis.specializationConstants;
+    let stages = constants
+        .numStages
+        .clamp(0, constants.stageRadix.len() as i32) as usize;
+    let source = if constants.code0.is_null() {
+        None
+    } else {
+        Some(
+            CStr::from_ptr(constants.code0)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    };
+    // Same tests as printDebugInformation in vkFFT_RunApp.h, on the pointers of the last launch.
+    let config = &app.configuration;
+    let name_of = |buffer| {
+        if buffer == config.inputBuffer && config.inputBuffer != config.buffer {
+            Some("input")
+        } else if buffer == config.buffer {
+            Some("buffer")
+        } else if buffer == config.tempBuffer {
+            Some("temp")
+        } else if buffer == config.outputBuffer && config.outputBuffer != config.buffer {
+            Some("output")
+        } else {
+            None
+        }
+    };
+    Upload {
+        size,
+        radices: constants.stageRadix[..stages]
+            .iter()
+            .map(|r| *r as u32)
+            .collect(),
+        name: CStr::from_ptr(axis.VkFFTFunctionName.as_ptr())
+            .to_string_lossy()
+            .into_owned(),
+        reads: if launched {
+            name_of(axis.inputBuffer)
+        } else {
+            None
+        },
+        writes: if launched {
+            name_of(axis.outputBuffer)
+        } else {
+            None
+        },
+        source,
+    }
+}
+
+impl fmt::Display for PlanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.temp_buffer {
+            Some(bytes) => writeln!(f, "temp buffer : {} bytes", bytes)?,
+            None => writeln!(f, "temp buffer : none")?,
+        }
+        for (direction, axes) in [("forward", &self.forward), ("inverse", &self.inverse)] {
+            for axis in axes {
+                writeln!(
+                    f,
+                    "{} axis {}{} :",
+                    direction,
+                    axis.axis,
+                    if axis.bluestein { " (Bluestein)" } else { "" }
+                )?;
+                for upload in &axis.uploads {
+                    write!(
+                        f,
+                        "  {} size {} radices {:?}",
+                        upload.name, upload.size, upload.radices
+                    )?;
+                    if let (Some(reads), Some(writes)) = (upload.reads, upload.writes) {
+                        write!(f, " {} -> {}", reads, writes)?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -6,16 +6,18 @@
 extern crate cl_sys;
 use cl_sys::{cl_platform_id, cl_command_queue, cl_mem, cl_device_id, cl_context, cl_program, cl_kernel};
 
+#[cfg(feature = "bindgen")]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+#[cfg(all(feature = "pregenerated-bindings", not(feature = "bindgen")))]
+include!("bindings.rs");
+#[cfg(not(any(feature = "pregenerated-bindings", feature = "bindgen")))]
+compile_error!("Enable either the `pregenerated-bindings` or the `bindgen` feature");
 
-unsafe extern "C" {
-    pub fn almost_initializeVkFFT(
-        app: *mut VkFFTApplication,
-        inputLaunchConfiguration: VkFFTConfiguration,
-    ) -> VkFFTResult;
-}
-
-pub fn say_hello() {
-    println!("{}", concat!(env!("OUT_DIR"), "/bindings.rs"));
-}
-
+pub mod convolution;
+pub mod diagnostics;
+pub mod plan;
+pub mod version;
+pub use convolution::{Convolution, ConvolutionBuilder};
+pub use diagnostics::{AxisReport, PlanReport, Upload};
+pub use plan::{LaunchError, Plan, PlanBuilder, PlanError, Transform, VkFFTError};
+pub use version::{vkfft_version, Version, OPENCL_TARGET};
@@ -0,0 +1,671 @@
+//! Safe wrapper around a VkFFT application on ocl buffers.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use cl_sys::{cl_context, cl_device_id, cl_mem};
+use ocl::enums::DeviceInfo;
+use ocl::ocl_core::ClDeviceIdPtr;
+use ocl::{Buffer, Context, Device, OclPrm, Queue};
+
+use crate::diagnostics::PlanReport;
+use crate::{
+    VkFFTApplication, VkFFTConfiguration, VkFFTLaunchParams, VkFFTResult, VkFFTResult_VKFFT_SUCCESS,
+};
+
+/// Non-zero `VkFFTResult` returned by VkFFT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VkFFTError(pub VkFFTResult);
+
+impl fmt::Display for VkFFTError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = unsafe { CStr::from_ptr(crate::getVkFFTErrorString(self.0)) };
+        write!(f, "VkFFT error {} : {}", self.0, name.to_string_lossy())
+    }
+}
+
+impl std::error::Error for VkFFTError {}
+
+/// Failure to build a plan.
+#[derive(Debug)]
+pub enum PlanError {
+    VkFFT(VkFFTError),
+    /// The device could not be queried for the cache key.
+    Device(ocl::Error),
+    /// The compiled plan could not be written to the cache.
+    Cache(std::io::Error),
+    /// The kernel of a convolution could not be transformed.
+    Launch(LaunchError),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanError::VkFFT(e) => write!(f, "{}", e),
+            PlanError::Device(e) => write!(f, "Device query failed : {}", e),
+            PlanError::Cache(e) => write!(f, "Plan cache : {}", e),
+            PlanError::Launch(e) => write!(f, "Kernel transform : {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+impl From<VkFFTError> for PlanError {
+    fn from(e: VkFFTError) -> PlanError {
+        PlanError::VkFFT(e)
+    }
+}
+
+/// Failure to enqueue a transform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LaunchError {
+    VkFFT(VkFFTError),
+    /// A buffer holds fewer bytes than the plan reads or writes. The check covers wrong element
+    /// types, e.g. `f32` buffers given to a complex plan.
+    BufferSize {
+        buffer: &'static str,
+        bytes: u64,
+        needed: u64,
+    },
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LaunchError::VkFFT(e) => write!(f, "{}", e),
+            LaunchError::BufferSize {
+                buffer,
+                bytes,
+                needed,
+            } => write!(
+                f,
+                "The {} buffer holds {} bytes, the plan needs {}",
+                buffer, bytes, needed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {}
+
+impl From<VkFFTError> for LaunchError {
+    fn from(e: VkFFTError) -> LaunchError {
+        LaunchError::VkFFT(e)
+    }
+}
+
+impl From<LaunchError> for PlanError {
+    fn from(e: LaunchError) -> PlanError {
+        PlanError::Launch(e)
+    }
+}
+
+/// Converts a VkFFT return code to a `Result`.
+pub fn check(res: VkFFTResult) -> Result<(), VkFFTError> {
+    if res == VkFFTResult_VKFFT_SUCCESS {
+        Ok(())
+    } else {
+        Err(VkFFTError(res))
+    }
+}
+
+/// Kind of transform of a plan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    /// Complex to complex FFT on interleaved (re, im) pairs.
+    Complex,
+    /// FFT of real data, of which only the `size[0] / 2 + 1` first complex outputs along axis 0
+    /// are stored. Out of place, the real input is not padded and the inverse writes it back, which
+    /// VkFFT supports for a single batch. In place, the real rows are padded to `size[0] + 2`
+    /// elements.
+    RealToComplex,
+    /// Real discrete cosine transform of type 1 to 4, its inverse is DCT-I, DCT-III, DCT-II and
+    /// DCT-IV respectively. Cosine series, for even (Neumann) boundaries.
+    Dct(u32),
+    /// Real discrete sine transform of type 1 to 4, for odd (Dirichlet) boundaries.
+    Dst(u32),
+}
+
+impl Transform {
+    /// Bytes of one element of the transformed buffers in single precision, of the complex
+    /// output of a real-to-complex transform.
+    pub fn element_size(&self) -> usize {
+        match self {
+            Transform::Complex | Transform::RealToComplex => 8,
+            Transform::Dct(_) | Transform::Dst(_) => 4,
+        }
+    }
+}
+
+/// FFT of up to 3 dimensions, see `Plan::builder`.
+#[derive(Clone, Debug)]
+pub struct PlanBuilder {
+    size: Vec<usize>,
+    transform: Transform,
+    omit: [bool; 3],
+    zero_padding: [Option<Range<usize>>; 3],
+    frequency_zero_padding: bool,
+    double_precision: bool,
+    batches: usize,
+    normalize: bool,
+    out_of_place: bool,
+    cache: Option<PathBuf>,
+    keep_shader_code: bool,
+    print_memory_layout: bool,
+}
+
+impl PlanBuilder {
+    /// Transforms of `count` contiguous arrays at once, of `len()` elements each.
+    pub fn batches(mut self, count: usize) -> PlanBuilder {
+        self.batches = count;
+        self
+    }
+
+    /// Real-to-real transforms use `f32` buffers, with the same normalization convention as FFTW :
+    /// the normalized inverse of the forward transform is the identity.
+    pub fn transform(mut self, transform: Transform) -> PlanBuilder {
+        if let Transform::Dct(kind) | Transform::Dst(kind) = transform {
+            assert!((1..=4).contains(&kind), "No DCT/DST of type {}", kind);
+        }
+        self.transform = transform;
+        self
+    }
+
+    /// Skips the transform along `axis`, e.g. omitting axis 1 of a 2D plan transforms each row
+    /// of `size[0]` elements independently. An omitted last axis still counts as a batch of arrays.
+    pub fn omit_dimension(mut self, axis: usize) -> PlanBuilder {
+        assert!(axis < self.size.len(), "No axis {} to omit", axis);
+        self.omit[axis] = true;
+        self
+    }
+
+    /// Elements `zeros` along `axis` are known to be zero : the forward transform neither reads
+    /// them nor computes the sequences made only of them. With `frequency_zero_padding`, the zero
+    /// block is in the spectrum instead, it is skipped when read by the inverse transform and left
+    /// unwritten by the forward transform.
+    pub fn zero_padding(mut self, axis: usize, zeros: Range<usize>) -> PlanBuilder {
+        assert!(axis < self.size.len(), "No axis {} to pad", axis);
+        assert!(
+            zeros.start < zeros.end && zeros.end <= self.size[axis],
+            "Zero block {:?} out of axis {} of {} elements",
+            zeros,
+            axis,
+            self.size[axis]
+        );
+        self.zero_padding[axis] = Some(zeros);
+        self
+    }
+
+    /// Whether the zero blocks of `zero_padding` are in the frequency domain, e.g. the modes added
+    /// by a padded grid for 3/2-rule dealiasing.
+    pub fn frequency_zero_padding(mut self, frequency: bool) -> PlanBuilder {
+        self.frequency_zero_padding = frequency;
+        self
+    }
+
+    /// Computes and stores in `f64` instead of `f32`, the device must support cl_khr_fp64.
+    pub fn double_precision(mut self, double: bool) -> PlanBuilder {
+        self.double_precision = double;
+        self
+    }
+
+    /// Whether the inverse transform is divided by the number of elements.
+    pub fn normalize(mut self, normalize: bool) -> PlanBuilder {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Whether the forward transform reads a different buffer than the one it writes.
+    pub fn out_of_place(mut self, out_of_place: bool) -> PlanBuilder {
+        self.out_of_place = out_of_place;
+        self
+    }
+
+    /// Directory where the compiled plan is saved, and loaded from on later runs instead of
+    /// compiling it again. Plans are keyed by their parameters, the device, its driver and the
+    /// VkFFT version.
+    pub fn cache(mut self, dir: impl Into<PathBuf>) -> PlanBuilder {
+        self.cache = Some(dir.into());
+        self
+    }
+
+    /// Keeps the generated kernel sources for `Plan::report`, and has VkFFT print each of them
+    /// to stdout when launched. Such plans are always compiled, never loaded from the cache.
+    pub fn keep_shader_code(mut self, keep: bool) -> PlanBuilder {
+        self.keep_shader_code = keep;
+        self
+    }
+
+    /// Has VkFFT print the buffers each kernel reads and writes to stdout, at every launch.
+    pub fn print_memory_layout(mut self, print: bool) -> PlanBuilder {
+        self.print_memory_layout = print;
+        self
+    }
+
+    /// File name of the plan in the cache, of `features` arrays per batch and with the
+    /// convolution options set by `configure`.
+    fn cache_key(
+        &self,
+        device: &Device,
+        features: usize,
+        configure: &impl Fn(&mut VkFFTConfiguration),
+    ) -> Result<String, PlanError> {
+        let name = device.name().map_err(PlanError::Device)?;
+        let driver = device
+            .info(DeviceInfo::DriverVersion)
+            .map_err(PlanError::Device)?;
+        let size = self
+            .size
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("x");
+        let omit = self.omit.map(|o| o as u8);
+        let padding = self
+            .zero_padding
+            .iter()
+            .map(|zeros| match zeros {
+                Some(zeros) => format!("{}-{}", zeros.start, zeros.end),
+                None => "0".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("x");
+        let mut config = VkFFTConfiguration::default();
+        configure(&mut config);
+        let convolution = [
+            config.performConvolution,
+            config.kernelConvolution,
+            config.matrixConvolution,
+            config.symmetricKernel,
+            config.conjugateConvolution,
+            config.crossPowerSpectrumNormalization,
+            config.numberKernels,
+        ]
+        .map(|x| x.to_string())
+        .join("x");
+        let key = format!(
+            "{}_{:?}_m{}{}{}_z{}{}_b{}_c{}_k{}_f{}_n{}_o{}_{}_{}_vkfft{}",
+            size,
+            self.transform,
+            omit[0],
+            omit[1],
+            omit[2],
+            padding,
+            if self.frequency_zero_padding {
+                "f"
+            } else {
+                "s"
+            },
+            self.batches,
+            features,
+            convolution,
+            if self.double_precision { 64 } else { 32 },
+            self.normalize as u8,
+            self.out_of_place as u8,
+            name,
+            driver,
+            crate::vkfft_version()
+        );
+        let key = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        Ok(key + ".vkfft")
+    }
+
+    pub fn build(self, context: &Context, device: Device) -> Result<Plan, PlanError> {
+        self.build_with(context, device, 1, None, |_| {})
+    }
+
+    /// Bytes of the buffer and of the input buffer of out of place plans.
+    fn buffer_bytes(&self, features: usize) -> (u64, u64) {
+        let len = self.size.iter().product::<usize>();
+        let precision = if self.double_precision { 2 } else { 1 };
+        let arrays = features * self.batches * precision;
+        let elements = match self.transform {
+            Transform::RealToComplex => len / self.size[0] * (self.size[0] / 2 + 1),
+            _ => len,
+        };
+        let bytes = self.transform.element_size() * elements * arrays;
+        let input_bytes = match self.transform {
+            Transform::RealToComplex if self.out_of_place => 4 * len * arrays,
+            _ => bytes,
+        };
+        (bytes as u64, input_bytes as u64)
+    }
+
+    /// Builds a plan of `features` arrays per batch, with the convolution kernel `kernel` of
+    /// the given size in bytes, after `configure` has set the remaining options.
+    pub(crate) fn build_with(
+        self,
+        context: &Context,
+        device: Device,
+        features: usize,
+        kernel: Option<(cl_mem, u64)>,
+        configure: impl Fn(&mut VkFFTConfiguration),
+    ) -> Result<Plan, PlanError> {
+        let cache_file = match &self.cache {
+            Some(dir) if !self.keep_shader_code => {
+                Some(dir.join(self.cache_key(&device, features, &configure)?))
+            }
+            _ => None,
+        };
+        // A missing, unreadable or truncated entry is compiled again.
+        if let Some(path) = &cache_file {
+            if let Some(cached) = read_cache(path) {
+                match self.initialize(
+                    context,
+                    device,
+                    features,
+                    kernel,
+                    Some(cached),
+                    false,
+                    &configure,
+                ) {
+                    Ok(plan) => return Ok(plan),
+                    // A corrupted entry, replaced by the compiled plan.
+                    Err(_) => {
+                        let _ = std::fs::remove_file(path);
+                    }
+                }
+            }
+        }
+
+        let save = cache_file.is_some();
+        let plan = self.initialize(context, device, features, kernel, None, save, &configure)?;
+        if let Some(path) = cache_file {
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    plan.app.saveApplicationString as *const u8,
+                    plan.app.applicationStringSize as usize,
+                )
+            };
+            write_cache(&path, bytes).map_err(PlanError::Cache)?;
+        }
+        Ok(plan)
+    }
+
+    /// Initializes the application from the `cached` compiled plan if given, or compiles it and
+    /// keeps the compiled plan in the application for the cache if `save` is set.
+    #[allow(clippy::too_many_arguments)]
+    fn initialize(
+        &self,
+        context: &Context,
+        device: Device,
+        features: usize,
+        kernel: Option<(cl_mem, u64)>,
+        mut cached: Option<Vec<u8>>,
+        save: bool,
+        configure: &impl Fn(&mut VkFFTConfiguration),
+    ) -> Result<Plan, PlanError> {
+        let len = self.size.iter().product::<usize>();
+        let mut size = [0; 4];
+        let mut omit = [0; 4];
+        let (mut padded, mut left, mut right) = ([0; 4], [0; 4], [0; 4]);
+        for (k, n) in self.size.iter().enumerate() {
+            size[k] = *n as u64;
+            omit[k] = self.omit[k] as u64;
+            if let Some(zeros) = &self.zero_padding[k] {
+                padded[k] = 1;
+                left[k] = zeros.start as u64;
+                right[k] = zeros.end as u64;
+            }
+        }
+        // VkFFT keeps these pointers for the lifetime of the application.
+        let mut device = Box::new(device.as_ptr());
+        let mut context = Box::new(context.as_ptr());
+        let (bytes, input_bytes) = self.buffer_bytes(features);
+        let mut buffer_size = Box::new(bytes);
+        let mut input_buffer_size = Box::new(input_bytes);
+        let mut kernel = kernel.map(|(buffer, bytes)| (Box::new(buffer), Box::new(bytes)));
+
+        let mut config = VkFFTConfiguration {
+            FFTdim: self.size.len() as u64,
+            size,
+            omitDimension: omit,
+            performZeropadding: padded,
+            fft_zeropad_left: left,
+            fft_zeropad_right: right,
+            frequencyZeroPadding: self.frequency_zero_padding as u64,
+            numberBatches: self.batches as u64,
+            coordinateFeatures: features as u64,
+            device: device.as_mut(),
+            context: context.as_mut(),
+            bufferSize: buffer_size.as_mut(),
+            inputBufferSize: input_buffer_size.as_mut(),
+            normalize: self.normalize as u64,
+            isInputFormatted: self.out_of_place as u64,
+            performR2C: (self.transform == Transform::RealToComplex) as u64,
+            doublePrecision: self.double_precision as u64,
+            performDCT: match self.transform {
+                Transform::Dct(kind) => kind as u64,
+                _ => 0,
+            },
+            performDST: match self.transform {
+                Transform::Dst(kind) => kind as u64,
+                _ => 0,
+            },
+            keepShaderCode: self.keep_shader_code as u64,
+            printMemoryLayout: self.print_memory_layout as u64,
+            saveApplicationToString: save as u64,
+            loadApplicationFromString: cached.is_some() as u64,
+            loadApplicationString: match cached.as_mut() {
+                Some(bytes) => bytes.as_mut_ptr() as *mut std::os::raw::c_void,
+                None => std::ptr::null_mut(),
+            },
+            ..Default::default()
+        };
+        if let Some((buffer, bytes)) = kernel.as_mut() {
+            config.kernel = buffer.as_mut();
+            config.kernelSize = bytes.as_mut();
+        }
+        configure(&mut config);
+        let mut app = Box::<VkFFTApplication>::default();
+        check(unsafe { crate::initializeVkFFT(app.as_mut(), config) })?;
+        // Only read during the initialization.
+        std::mem::drop(cached);
+
+        Ok(Plan {
+            app,
+            _device: device,
+            _context: context,
+            buffer_size,
+            input_buffer_size,
+            kernel,
+            len,
+            batches: self.batches,
+            last_direction: None,
+        })
+    }
+}
+
+/// Reads a cache entry, `None` unless it ends with the length written by `write_cache`.
+fn read_cache(path: &Path) -> Option<Vec<u8>> {
+    let mut bytes = std::fs::read(path).ok()?;
+    let split = bytes.len().checked_sub(8)?;
+    let len = u64::from_le_bytes(bytes[split..].try_into().ok()?);
+    if len != split as u64 {
+        return None;
+    }
+    bytes.truncate(split);
+    Some(bytes)
+}
+
+/// Writes a cache entry followed by its length, to a temporary file renamed over `path`, so that
+/// killed or concurrent runs never leave a partial entry.
+fn write_cache(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+/// An initialized VkFFT application, deleted on drop.
+pub struct Plan {
+    app: Box<VkFFTApplication>,
+    _device: Box<cl_device_id>,
+    _context: Box<cl_context>,
+    buffer_size: Box<u64>,
+    input_buffer_size: Box<u64>,
+    kernel: Option<(Box<cl_mem>, Box<u64>)>,
+    len: usize,
+    batches: usize,
+    last_direction: Option<i32>,
+}
+
+impl Plan {
+    /// A plan of the given dimensions, one batch, normalized inverse, in place.
+    /// `size[0]` is the contiguous axis : a row-major `[n1][n0]` array has size `[n0, n1]`.
+    pub fn builder(size: &[usize]) -> PlanBuilder {
+        assert!(
+            (1..=3).contains(&size.len()),
+            "VkFFT plans have 1 to 3 dimensions"
+        );
+        PlanBuilder {
+            size: size.to_vec(),
+            transform: Transform::Complex,
+            omit: [false; 3],
+            zero_padding: [None, None, None],
+            frequency_zero_padding: false,
+            double_precision: false,
+            batches: 1,
+            normalize: true,
+            out_of_place: false,
+            cache: None,
+            keep_shader_code: false,
+            print_memory_layout: false,
+        }
+    }
+
+    /// Elements of one batch.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn batches(&self) -> usize {
+        self.batches
+    }
+
+    /// Enqueues the forward transform of `input` into `output`, which may be the same buffer.
+    /// The element types differ for real-to-complex plans, e.g. `f32` and `Complex32`. Buffers
+    /// smaller than the plan are rejected before the launch.
+    pub fn forward<I: OclPrm, O: OclPrm>(
+        &mut self,
+        queue: &Queue,
+        input: &Buffer<I>,
+        output: &Buffer<O>,
+    ) -> Result<(), LaunchError> {
+        self.append(queue, -1, input, output)
+    }
+
+    /// Enqueues the in place inverse transform of `buffer`.
+    pub fn inverse<T: OclPrm>(
+        &mut self,
+        queue: &Queue,
+        buffer: &Buffer<T>,
+    ) -> Result<(), LaunchError> {
+        self.append(queue, 1, buffer, buffer)
+    }
+
+    /// Enqueues the inverse transform of `spectrum` into `output`, for out of place plans : the
+    /// inverse writes the buffer the forward transform reads.
+    pub fn inverse_into<I: OclPrm, O: OclPrm>(
+        &mut self,
+        queue: &Queue,
+        spectrum: &Buffer<O>,
+        output: &Buffer<I>,
+    ) -> Result<(), LaunchError> {
+        self.append(queue, 1, output, spectrum)
+    }
+
+    fn append<I: OclPrm, O: OclPrm>(
+        &mut self,
+        queue: &Queue,
+        direction: i32,
+        input: &Buffer<I>,
+        output: &Buffer<O>,
+    ) -> Result<(), LaunchError> {
+        self.append_with_kernel(queue, direction, input, output, None)
+    }
+
+    pub(crate) fn append_with_kernel<I: OclPrm, O: OclPrm>(
+        &mut self,
+        queue: &Queue,
+        direction: i32,
+        input: &Buffer<I>,
+        output: &Buffer<O>,
+        kernel: Option<&Buffer<O>>,
+    ) -> Result<(), LaunchError> {
+        let bytes = |len: usize, size: usize| (len * size) as u64;
+        let mut sizes = vec![
+            (
+                "input",
+                bytes(input.len(), std::mem::size_of::<I>()),
+                *self.input_buffer_size,
+            ),
+            (
+                "output",
+                bytes(output.len(), std::mem::size_of::<O>()),
+                *self.buffer_size,
+            ),
+        ];
+        if let (Some(kernel), Some((_, needed))) = (kernel, self.kernel.as_ref()) {
+            sizes.push((
+                "kernel",
+                bytes(kernel.len(), std::mem::size_of::<O>()),
+                **needed,
+            ));
+        }
+        for (buffer, bytes, needed) in sizes {
+            if bytes < needed {
+                return Err(LaunchError::BufferSize {
+                    buffer,
+                    bytes,
+                    needed,
+                });
+            }
+        }
+        let mut launch = VkFFTLaunchParams {
+            commandQueue: &mut queue.as_ptr(),
+            inputBuffer: &mut input.as_ptr(),
+            buffer: &mut output.as_ptr(),
+            ..Default::default()
+        };
+        let mut kernel = kernel.map(|k| k.as_ptr());
+        if let Some(kernel) = kernel.as_mut() {
+            launch.kernel = kernel;
+        }
+        self.last_direction = Some(direction);
+        check(unsafe { crate::VkFFTAppend(self.app.as_mut(), direction, &mut launch) })?;
+        Ok(())
+    }
+
+    /// Kernels, radices, memory layout and temporary buffer VkFFT chose for this plan.
+    pub fn report(&self) -> PlanReport {
+        unsafe { PlanReport::new(&self.app, self.last_direction) }
+    }
+
+    /// The underlying application, for launches the wrapper does not cover.
+    pub fn app(&mut self) -> &mut VkFFTApplication {
+        self.app.as_mut()
+    }
+}
+
+impl Drop for Plan {
+    fn drop(&mut self) {
+        unsafe {
+            crate::deleteVkFFT(self.app.as_mut());
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Versions of the vendored VkFFT and of the OpenCL headers it was compiled against.
+
+use std::fmt;
+
+/// A VkFFT release.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// Unpacks the X.XX.XX format of `VkFFTGetVersion`.
+    pub fn from_packed(version: u32) -> Version {
+        Version {
+            major: version / 10000,
+            minor: version / 100 % 100,
+            patch: version % 100,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Version of the VkFFT compiled into the crate, the build fails unless it is the pinned release.
+pub fn vkfft_version() -> Version {
+    Version::from_packed(unsafe { crate::VkFFTGetVersion() } as u32)
+}
+
+/// CL_TARGET_OPENCL_VERSION of the compiled VkFFT, selected by the `opencl-1-2` and
+/// `opencl-2-0` features.
+pub const OPENCL_TARGET: (u32, u32) = if cfg!(feature = "opencl-1-2") {
+    (1, 2)
+} else if cfg!(feature = "opencl-2-0") {
+    (2, 0)
+} else {
+    (3, 0)
+};
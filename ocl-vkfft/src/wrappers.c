@@ -0,0 +1,9 @@
+#include "vkFFT/vkFFT.h"
+
+// Static wrappers
+
+VkFFTResult initializeVkFFT__extern(VkFFTApplication *app, VkFFTConfiguration inputLaunchConfiguration) { return initializeVkFFT(app, inputLaunchConfiguration); }
+VkFFTResult VkFFTAppend__extern(VkFFTApplication *app, int inverse, VkFFTLaunchParams *launchParams) { return VkFFTAppend(app, inverse, launchParams); }
+void deleteVkFFT__extern(VkFFTApplication *app) { deleteVkFFT(app); }
+const char *getVkFFTErrorString__extern(VkFFTResult result) { return getVkFFTErrorString(result); }
+int VkFFTGetVersion__extern(void) { return VkFFTGetVersion(); }
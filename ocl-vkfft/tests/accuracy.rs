@@ -0,0 +1,255 @@
+//! Transforms against rustfft on pseudo-random data : powers of two, primes, real-to-complex
+//! transforms, batches, single and double precision. Runs on PoCL when installed, skipped
+//! without an OpenCL platform.
+//!
+//! Errors are the largest difference to the reference relative to the largest reference
+//! magnitude, for the forward transform and for the normalized round trip. The reference is
+//! computed by rustfft in f64, so the tolerances bound the error of VkFFT alone. Radix
+//! transforms lose about ε log2(n). Primes from 17 go through Rader's or, above 16384,
+//! Bluestein's algorithm, both convolutions that lose about one more digit.
+
+mod common;
+
+use common::{complex_signal, device, max_error, read, reference, signal, supports_f64, upload};
+use num_complex::{Complex32, Complex64};
+use ocl::{Buffer, OclPrm};
+use ocl_vkfft::{Plan, PlanBuilder, Transform};
+
+/// Radix transforms in single precision, ε = 6e-8 and log2(n) ≤ 16.
+const F32: f64 = 1e-5;
+/// Rader and Bluestein transforms in single precision.
+const F32_PRIME: f64 = 1e-4;
+/// Radix transforms in double precision, ε = 1.1e-16.
+const F64: f64 = 1e-13;
+/// Rader and Bluestein transforms in double precision.
+const F64_PRIME: f64 = 1e-12;
+
+/// Element types of a precision.
+trait Precision {
+    type Real: OclPrm;
+    type Complex: OclPrm;
+    const DOUBLE: bool;
+    fn real(x: f64) -> Self::Real;
+    fn complex(x: Complex64) -> Self::Complex;
+    fn widen_real(x: Self::Real) -> f64;
+    fn widen(x: Self::Complex) -> Complex64;
+}
+
+struct Single;
+struct Double;
+
+impl Precision for Single {
+    type Real = f32;
+    type Complex = Complex32;
+    const DOUBLE: bool = false;
+    fn real(x: f64) -> f32 {
+        x as f32
+    }
+    fn complex(x: Complex64) -> Complex32 {
+        Complex32::new(x.re as f32, x.im as f32)
+    }
+    fn widen_real(x: f32) -> f64 {
+        x as f64
+    }
+    fn widen(x: Complex32) -> Complex64 {
+        Complex64::new(x.re as f64, x.im as f64)
+    }
+}
+
+impl Precision for Double {
+    type Real = f64;
+    type Complex = Complex64;
+    const DOUBLE: bool = true;
+    fn real(x: f64) -> f64 {
+        x
+    }
+    fn complex(x: Complex64) -> Complex64 {
+        x
+    }
+    fn widen_real(x: f64) -> f64 {
+        x
+    }
+    fn widen(x: Complex64) -> Complex64 {
+        x
+    }
+}
+
+/// Plan of `builder` in the precision of `P`, `None` when the device cannot run it.
+fn plan<P: Precision>(builder: PlanBuilder) -> Option<(Plan, ocl::Queue)> {
+    let (context, device, queue) = device()?;
+    if P::DOUBLE && !supports_f64(&device) {
+        return None;
+    }
+    let plan = builder
+        .double_precision(P::DOUBLE)
+        .build(&context, device)
+        .unwrap();
+    Some((plan, queue))
+}
+
+/// In place forward transform of `input` by `builder`, and normalized inverse of the result.
+fn run_in_place<P: Precision, T: OclPrm>(
+    builder: PlanBuilder,
+    input: &[T],
+) -> Option<(Vec<T>, Vec<T>)> {
+    let (mut plan, queue) = plan::<P>(builder)?;
+    let buffer = upload(&queue, input);
+    plan.forward(&queue, &buffer, &buffer).unwrap();
+    let forward = read(&buffer);
+    plan.inverse(&queue, &buffer).unwrap();
+    Some((forward, read(&buffer)))
+}
+
+/// Out of place forward transform of `input` by `builder`, and normalized inverse of the result.
+fn run_out_of_place<P: Precision, I: OclPrm, O: OclPrm>(
+    builder: PlanBuilder,
+    input: &[I],
+    spectrum_len: usize,
+) -> Option<(Vec<O>, Vec<I>)> {
+    let (mut plan, queue) = plan::<P>(builder.out_of_place(true))?;
+    let input_buffer = upload(&queue, input);
+    let spectrum = Buffer::<O>::builder()
+        .queue(queue.clone())
+        .len(spectrum_len)
+        .build()
+        .unwrap();
+    plan.forward(&queue, &input_buffer, &spectrum).unwrap();
+    // Read before the inverse, which may use the spectrum as scratch space.
+    let forward = read(&spectrum);
+    plan.inverse_into(&queue, &spectrum, &input_buffer).unwrap();
+    Some((forward, read(&input_buffer)))
+}
+
+fn check_complex<P: Precision>(size: &[usize], batches: usize, tolerance: f64) {
+    let input = complex_signal(size.iter().product::<usize>() * batches);
+    let host = input.iter().map(|x| P::complex(*x)).collect::<Vec<_>>();
+    let builder = Plan::builder(size).batches(batches);
+    let Some((forward, back)) = run_in_place::<P, P::Complex>(builder, &host) else {
+        return;
+    };
+    let forward = forward.into_iter().map(P::widen).collect::<Vec<_>>();
+    let back = back.into_iter().map(P::widen).collect::<Vec<_>>();
+    let axes = (0..size.len()).collect::<Vec<_>>();
+    let error = max_error(&forward, &reference(&input, size, &axes));
+    assert!(
+        error < tolerance,
+        "{:?} x {} forward : relative error {:e}",
+        size,
+        batches,
+        error
+    );
+    let error = max_error(&back, &input);
+    assert!(
+        error < tolerance,
+        "{:?} x {} round trip : relative error {:e}",
+        size,
+        batches,
+        error
+    );
+}
+
+/// Out of place, which VkFFT only supports for a single batch.
+fn check_real<P: Precision>(size: &[usize], tolerance: f64) {
+    let len = size.iter().product::<usize>();
+    let input = signal(len);
+    let host = input.iter().map(|x| P::real(*x)).collect::<Vec<_>>();
+    // The non-redundant half of axis 0.
+    let half = size[0] / 2 + 1;
+    let builder = Plan::builder(size).transform(Transform::RealToComplex);
+    let spectrum_len = len / size[0] * half;
+    let Some((forward, back)) =
+        run_out_of_place::<P, P::Real, P::Complex>(builder, &host, spectrum_len)
+    else {
+        return;
+    };
+    let forward = forward.into_iter().map(P::widen).collect::<Vec<_>>();
+    let back = back
+        .into_iter()
+        .map(|x| Complex64::new(P::widen_real(x), 0.0))
+        .collect::<Vec<_>>();
+    let complex_input = input
+        .iter()
+        .map(|x| Complex64::new(*x, 0.0))
+        .collect::<Vec<_>>();
+    let axes = (0..size.len()).collect::<Vec<_>>();
+    let expected = reference(&complex_input, size, &axes)
+        .into_iter()
+        .enumerate()
+        .filter(|(k, _)| k % size[0] < half)
+        .map(|(_, x)| x)
+        .collect::<Vec<_>>();
+    let error = max_error(&forward, &expected);
+    assert!(
+        error < tolerance,
+        "{:?} real forward : relative error {:e}",
+        size,
+        error
+    );
+    let error = max_error(&back, &complex_input);
+    assert!(
+        error < tolerance,
+        "{:?} real round trip : relative error {:e}",
+        size,
+        error
+    );
+}
+
+#[test]
+fn powers_of_two() {
+    for size in [
+        &[2][..],
+        &[64],
+        &[4096],
+        &[65536],
+        &[256, 128],
+        &[32, 16, 8],
+    ] {
+        check_complex::<Single>(size, 1, F32);
+    }
+}
+
+#[test]
+fn small_primes_and_composites() {
+    // Radix 3, 5, 7, 11 and 13 kernels.
+    for size in [&[3][..], &[7], &[13], &[105], &[1000], &[45, 12]] {
+        check_complex::<Single>(size, 1, F32);
+    }
+}
+
+#[test]
+fn primes() {
+    // Rader up to 16384, Bluestein above.
+    for size in [&[17][..], &[127], &[1031], &[65537], &[17, 31]] {
+        check_complex::<Single>(size, 1, F32_PRIME);
+    }
+}
+
+#[test]
+fn batches() {
+    for batches in [1, 3, 16] {
+        check_complex::<Single>(&[64], batches, F32);
+    }
+    check_complex::<Single>(&[32, 32], 4, F32);
+    check_complex::<Single>(&[17], 5, F32_PRIME);
+}
+
+#[test]
+fn double_precision() {
+    for size in [&[64][..], &[4096], &[105], &[256, 128], &[32, 16, 8]] {
+        check_complex::<Double>(size, 1, F64);
+    }
+    for size in [&[127][..], &[65537]] {
+        check_complex::<Double>(size, 1, F64_PRIME);
+    }
+    check_complex::<Double>(&[64], 3, F64);
+}
+
+#[test]
+fn real_to_complex() {
+    for size in [&[64][..], &[4096], &[96, 32], &[32, 16, 8]] {
+        check_real::<Single>(size, F32);
+    }
+    check_real::<Single>(&[17], F32_PRIME);
+    check_real::<Double>(&[64], F64);
+    check_real::<Double>(&[96, 32], F64);
+}
@@ -0,0 +1,152 @@
+//! Device, test signals and reference transforms shared by the integration tests.
+
+// Each test crate uses part of it.
+#![allow(dead_code)]
+
+use num_complex::{Complex32, Complex64};
+use ocl::enums::DeviceInfo;
+use ocl::{Buffer, Context, Device, OclPrm, Platform, Queue};
+use rustfft::FftPlanner;
+use std::cell::Cell;
+use std::io::Write;
+
+/// PoCL, a CPU implementation that runs where no GPU is available, e.g. in CI.
+const POCL: &str = "Portable Computing Language";
+
+thread_local! {
+    static SKIPPED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Reports a test that returns without checking everything, once per test. Written to stderr
+/// directly, which the test harness does not capture unlike `eprintln!`, so that a run without
+/// a device does not look like a run where everything passed.
+pub fn skip(reason: &str) {
+    if SKIPPED.replace(true) {
+        return;
+    }
+    let thread = std::thread::current();
+    let test = thread.name().unwrap_or("test");
+    let _ = writeln!(std::io::stderr(), "{} skipped : {}", test, reason);
+}
+
+/// The first device of PoCL if installed, else of the first platform. `None` without OpenCL,
+/// after reporting the test as skipped.
+pub fn device() -> Option<(Context, Device, Queue)> {
+    if ocl::core::get_platform_ids().map_or(true, |p| p.is_empty()) {
+        skip("no OpenCL platform");
+        return None;
+    }
+    let platforms = Platform::list();
+    let platform = platforms
+        .iter()
+        .find(|p| p.name().is_ok_and(|name| name.contains(POCL)))
+        .or(platforms.first())
+        .cloned()?;
+    let Ok(device) = Device::first(platform) else {
+        skip("no OpenCL device");
+        return None;
+    };
+    let context = Context::builder()
+        .platform(platform)
+        .devices(device)
+        .build()
+        .expect("OpenCL context");
+    let queue = Queue::new(&context, device, None).expect("OpenCL queue");
+    Some((context, device, queue))
+}
+
+/// Whether `device` computes in double precision.
+pub fn supports_f64(device: &Device) -> bool {
+    let supported = device
+        .info(DeviceInfo::Extensions)
+        .is_ok_and(|extensions| extensions.to_string().contains("cl_khr_fp64"));
+    if !supported {
+        skip("no cl_khr_fp64 for double precision");
+    }
+    supported
+}
+
+/// Deterministic pseudo-random values in [-1, 1).
+pub fn signal(len: usize) -> Vec<f64> {
+    let mut state = 0x9e3779b97f4a7c15u64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+        })
+        .collect()
+}
+
+pub fn complex_signal(len: usize) -> Vec<Complex64> {
+    let values = signal(2 * len);
+    values
+        .chunks(2)
+        .map(|c| Complex64::new(c[0], c[1]))
+        .collect()
+}
+
+pub fn narrow(data: &[Complex64]) -> Vec<Complex32> {
+    data.iter()
+        .map(|x| Complex32::new(x.re as f32, x.im as f32))
+        .collect()
+}
+
+pub fn widen(data: &[Complex32]) -> Vec<Complex64> {
+    data.iter()
+        .map(|x| Complex64::new(x.re as f64, x.im as f64))
+        .collect()
+}
+
+/// Unnormalized forward FFT along each axis in `axes`, `size[0]` contiguous, batches after the
+/// last axis.
+pub fn reference(data: &[Complex64], size: &[usize], axes: &[usize]) -> Vec<Complex64> {
+    let mut planner = FftPlanner::<f64>::new();
+    let mut out = data.to_vec();
+    for &axis in axes {
+        let n = size[axis];
+        let fft = planner.plan_fft_forward(n);
+        let stride = size[..axis].iter().product::<usize>();
+        let mut line = vec![Complex64::new(0.0, 0.0); n];
+        // Each line starts at an element of index 0 along the axis.
+        for start in (0..out.len()).filter(|k| (k / stride) % n == 0) {
+            for (t, x) in line.iter_mut().enumerate() {
+                *x = out[start + t * stride];
+            }
+            fft.process(&mut line);
+            for (t, x) in line.iter().enumerate() {
+                out[start + t * stride] = *x;
+            }
+        }
+    }
+    out
+}
+
+/// Largest difference relative to the largest magnitude of `b`.
+pub fn max_error(a: &[Complex64], b: &[Complex64]) -> f64 {
+    assert_eq!(a.len(), b.len());
+    let scale = b.iter().map(|x| x.norm()).fold(0.0, f64::max);
+    let error = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).norm())
+        .fold(0.0, f64::max);
+    error / scale
+}
+
+/// Device buffer holding a copy of `data`.
+pub fn upload<T: OclPrm>(queue: &Queue, data: &[T]) -> Buffer<T> {
+    Buffer::<T>::builder()
+        .queue(queue.clone())
+        .len(data.len())
+        .copy_host_slice(data)
+        .build()
+        .unwrap()
+}
+
+pub fn read<T: OclPrm>(buffer: &Buffer<T>) -> Vec<T> {
+    let mut host = vec![T::default(); buffer.len()];
+    buffer.read(&mut host).enq().unwrap();
+    host
+}
@@ -0,0 +1,162 @@
+//! Fused convolutions by a delta and a shifted delta against the direct circular convolution, of
+//! single fields and of 2-feature fields by full and symmetric kernel matrices. Skipped when no
+//! OpenCL platform is available.
+
+mod common;
+
+use common::{complex_signal, device, max_error, narrow, read, upload, widen};
+use num_complex::{Complex32, Complex64};
+use ocl_vkfft::Convolution;
+
+/// Direct circular convolution of each batch of `x` by `kernel`, `size[0]` contiguous.
+fn circular_convolution(x: &[Complex64], kernel: &[Complex64], size: &[usize]) -> Vec<Complex64> {
+    let len = size.iter().product::<usize>();
+    let index = |k: usize| {
+        let mut rest = k;
+        size.iter()
+            .map(|n| {
+                let i = rest % n;
+                rest /= n;
+                i
+            })
+            .collect::<Vec<_>>()
+    };
+    let flat = |i: &[usize]| {
+        i.iter()
+            .zip(size)
+            .rev()
+            .fold(0, |flat, (i, n)| flat * n + i)
+    };
+    let mut out = vec![Complex64::new(0.0, 0.0); x.len()];
+    for (batch, out) in out.chunks_mut(len).enumerate() {
+        let x = &x[batch * len..(batch + 1) * len];
+        for (k, y) in out.iter_mut().enumerate() {
+            let k = index(k);
+            for (m, v) in x.iter().enumerate() {
+                let shift = index(m)
+                    .iter()
+                    .zip(&k)
+                    .zip(size)
+                    .map(|((m, k), n)| (k + n - m) % n)
+                    .collect::<Vec<_>>();
+                *y += v * kernel[flat(&shift)];
+            }
+        }
+    }
+    out
+}
+
+/// Kernel 1 at `shift` and 0 elsewhere.
+fn delta(size: &[usize], shift: &[usize]) -> Vec<Complex64> {
+    let len = size.iter().product::<usize>();
+    let at = shift
+        .iter()
+        .zip(size)
+        .rev()
+        .fold(0, |flat, (i, n)| flat * n + i);
+    (0..len)
+        .map(|k| Complex64::new((k == at) as i32 as f64, 0.0))
+        .collect()
+}
+
+fn check(size: &[usize], shift: &[usize], batches: usize) {
+    let Some((context, device, queue)) = device() else {
+        return;
+    };
+    let len = size.iter().product::<usize>();
+    let kernel = delta(size, shift);
+    let input = complex_signal(len * batches);
+    let kernel_buffer = upload(&queue, &narrow(&kernel));
+    let mut convolution = Convolution::<Complex32>::builder(size)
+        .batches(batches)
+        .build(&context, device, &queue, &kernel_buffer)
+        .unwrap();
+    let buffer = upload(&queue, &narrow(&input));
+    convolution.apply(&queue, &buffer).unwrap();
+
+    let expected = circular_convolution(&input, &kernel, size);
+    let error = max_error(&widen(&read(&buffer)), &expected);
+    assert!(
+        error < 1e-5,
+        "{:?} x {} by a delta at {:?} : relative error {:e}",
+        size,
+        batches,
+        shift,
+        error
+    );
+}
+
+/// Convolution of 2 features by a matrix of deltas at `shifts`, given row by row, or as its upper
+/// triangle when `symmetric`. Feature i of the output is Σ_j k_ij * x_j.
+fn check_matrix(size: &[usize], shifts: &[&[usize]], symmetric: bool) {
+    let Some((context, device, queue)) = device() else {
+        return;
+    };
+    let len = size.iter().product::<usize>();
+    let given = shifts
+        .iter()
+        .map(|shift| delta(size, shift))
+        .collect::<Vec<_>>();
+    let matrix = if symmetric {
+        vec![&given[0], &given[1], &given[1], &given[2]]
+    } else {
+        given.iter().collect()
+    };
+    let input = complex_signal(2 * len);
+    let builder = Convolution::<Complex32>::builder(size)
+        .features(2)
+        .matrix(true)
+        .symmetric(symmetric);
+    assert_eq!(builder.kernel_features(), given.len());
+    let kernel_buffer = upload(&queue, &narrow(&given.concat()));
+    let mut convolution = builder
+        .build(&context, device, &queue, &kernel_buffer)
+        .unwrap();
+    let buffer = upload(&queue, &narrow(&input));
+    convolution.apply(&queue, &buffer).unwrap();
+
+    let mut expected = vec![Complex64::new(0.0, 0.0); 2 * len];
+    for (i, out) in expected.chunks_mut(len).enumerate() {
+        for (j, x) in input.chunks(len).enumerate() {
+            let term = circular_convolution(x, matrix[2 * i + j], size);
+            for (y, t) in out.iter_mut().zip(term) {
+                *y += t;
+            }
+        }
+    }
+    let error = max_error(&widen(&read(&buffer)), &expected);
+    assert!(
+        error < 1e-5,
+        "{:?} by the {} matrix of deltas at {:?} : relative error {:e}",
+        size,
+        if symmetric { "symmetric" } else { "full" },
+        shifts,
+        error
+    );
+}
+
+#[test]
+fn delta_is_identity() {
+    check(&[32], &[0], 1);
+    check(&[16, 8], &[0, 0], 1);
+    check(&[16, 8], &[0, 0], 3);
+}
+
+#[test]
+fn shifted_delta_is_shift() {
+    check(&[32], &[5], 1);
+    check(&[16, 8], &[3, 7], 1);
+    check(&[8, 4, 4], &[1, 2, 3], 2);
+}
+
+#[test]
+fn matrix_of_deltas() {
+    check_matrix(&[32], &[&[0], &[5], &[2], &[0]], false);
+    check_matrix(&[16, 8], &[&[1, 0], &[3, 7], &[0, 2], &[5, 5]], false);
+}
+
+#[test]
+fn symmetric_matrix_of_deltas() {
+    check_matrix(&[32], &[&[2], &[5], &[9]], true);
+    check_matrix(&[16, 8], &[&[0, 0], &[3, 7], &[1, 2]], true);
+}
@@ -0,0 +1,234 @@
+//! Plans of 1 to 3 dimensions against rustfft, and DCT/DST plans against naive sums. Skipped when
+//! no OpenCL platform is available.
+
+use common::{complex_signal, device, max_error, narrow, read, reference, signal, upload, widen};
+use num_complex::{Complex32, Complex64};
+use ocl::Buffer;
+use ocl_vkfft::{LaunchError, Plan, PlanBuilder, Transform};
+use std::f64::consts::PI;
+
+mod common;
+
+/// Runs the forward transform of `builder` on `input`, in place.
+fn forward(builder: PlanBuilder, input: &[Complex32]) -> Option<Vec<Complex32>> {
+    let (context, device, queue) = device()?;
+    let mut plan = builder.build(&context, device).unwrap();
+    let buffer = upload(&queue, input);
+    plan.forward(&queue, &buffer, &buffer).unwrap();
+    Some(read(&buffer))
+}
+
+fn check_forward(size: &[usize], axes: &[usize], batches: usize, builder: PlanBuilder) {
+    let len = size.iter().product::<usize>() * batches;
+    let input = complex_signal(len);
+    let mut full_size = size.to_vec();
+    full_size.push(batches);
+    let expected = reference(&input, &full_size, axes);
+    if let Some(output) = forward(builder, &narrow(&input)) {
+        let error = max_error(&widen(&output), &expected);
+        assert!(error < 1e-4, "{:?} : relative error {}", size, error);
+    }
+}
+
+/// Unnormalized DCT-II, or DST-II if `sine`, along each axis in `axes` as FFTW's REDFT10 and
+/// RODFT10 : y_k = 2 Σ x_j cos(π (j + ½) k / n), or 2 Σ x_j sin(π (j + ½) (k + 1) / n).
+fn real_reference(data: &[f64], size: &[usize], axes: &[usize], sine: bool) -> Vec<f64> {
+    let mut out = data.to_vec();
+    for &axis in axes {
+        let n = size[axis];
+        let stride = size[..axis].iter().product::<usize>();
+        let input = out.clone();
+        for (index, y) in out.iter_mut().enumerate() {
+            let k = (index / stride) % n;
+            let start = index - k * stride;
+            *y = (0..n)
+                .map(|j| {
+                    let x = input[start + j * stride];
+                    let phase = PI * (j as f64 + 0.5) / n as f64;
+                    if sine {
+                        2.0 * x * (phase * (k + 1) as f64).sin()
+                    } else {
+                        2.0 * x * (phase * k as f64).cos()
+                    }
+                })
+                .sum();
+        }
+    }
+    out
+}
+
+/// Forward transform against `real_reference` and normalized round trip, in place.
+fn check_real_to_real(size: &[usize], axes: &[usize], transform: Transform) {
+    let Some((context, device, queue)) = device() else {
+        return;
+    };
+    let mut builder = Plan::builder(size).transform(transform);
+    for axis in (0..size.len()).filter(|a| !axes.contains(a)) {
+        builder = builder.omit_dimension(axis);
+    }
+    let mut plan = builder.build(&context, device).unwrap();
+    let input = signal(size.iter().product());
+    let host = input.iter().map(|x| *x as f32).collect::<Vec<_>>();
+    let buffer = upload(&queue, &host);
+    let as_complex = |data: &[f64]| {
+        data.iter()
+            .map(|x| Complex64::new(*x, 0.0))
+            .collect::<Vec<_>>()
+    };
+    let widen_real = |data: Vec<f32>| data.into_iter().map(|x| x as f64).collect::<Vec<_>>();
+
+    plan.forward(&queue, &buffer, &buffer).unwrap();
+    let forward = widen_real(read(&buffer));
+    let expected = real_reference(&input, size, axes, matches!(transform, Transform::Dst(_)));
+    let error = max_error(&as_complex(&forward), &as_complex(&expected));
+    assert!(
+        error < 1e-4,
+        "{:?} {:?} forward : relative error {}",
+        size,
+        transform,
+        error
+    );
+
+    plan.inverse(&queue, &buffer).unwrap();
+    let back = widen_real(read(&buffer));
+    let error = max_error(&as_complex(&back), &as_complex(&input));
+    assert!(
+        error < 1e-5,
+        "{:?} {:?} round trip : relative error {}",
+        size,
+        transform,
+        error
+    );
+}
+
+#[test]
+fn forward_1d() {
+    check_forward(&[64], &[0], 1, Plan::builder(&[64]));
+    check_forward(&[48], &[0], 1, Plan::builder(&[48]));
+}
+
+#[test]
+fn forward_2d() {
+    check_forward(&[32, 16], &[0, 1], 1, Plan::builder(&[32, 16]));
+}
+
+#[test]
+fn forward_3d() {
+    check_forward(&[8, 16, 4], &[0, 1, 2], 1, Plan::builder(&[8, 16, 4]));
+}
+
+#[test]
+fn batched_1d() {
+    check_forward(&[16], &[0], 3, Plan::builder(&[16]).batches(3));
+}
+
+#[test]
+fn omitted_dimensions() {
+    check_forward(&[32, 8], &[0], 1, Plan::builder(&[32, 8]).omit_dimension(1));
+    check_forward(&[32, 8], &[1], 1, Plan::builder(&[32, 8]).omit_dimension(0));
+    check_forward(
+        &[8, 4, 16],
+        &[0, 2],
+        1,
+        Plan::builder(&[8, 4, 16]).omit_dimension(1),
+    );
+}
+
+#[test]
+fn normalized_round_trip() {
+    let Some((context, device, queue)) = device() else {
+        return;
+    };
+    let input = narrow(&complex_signal(16 * 16 * 8));
+    let mut plan = Plan::builder(&[16, 16, 8]).build(&context, device).unwrap();
+    let buffer = upload(&queue, &input);
+    plan.forward(&queue, &buffer, &buffer).unwrap();
+    plan.inverse(&queue, &buffer).unwrap();
+    let error = max_error(&widen(&read(&buffer)), &widen(&input));
+    assert!(error < 1e-5, "relative error {}", error);
+}
+
+#[test]
+fn dct_dst_fftw_convention() {
+    for transform in [Transform::Dct(2), Transform::Dst(2)] {
+        check_real_to_real(&[16], &[0], transform);
+        check_real_to_real(&[12], &[0], transform);
+        // The axes of the free-slip solver : both, or one at a time.
+        check_real_to_real(&[16, 8], &[0, 1], transform);
+        check_real_to_real(&[16, 8], &[0], transform);
+        check_real_to_real(&[16, 8], &[1], transform);
+    }
+}
+
+#[test]
+fn plan_report() {
+    let Some((context, device, queue)) = device() else {
+        return;
+    };
+    let mut plan = Plan::builder(&[64, 32])
+        .keep_shader_code(true)
+        .build(&context, device)
+        .unwrap();
+    let buffer = upload(&queue, &narrow(&complex_signal(plan.len())));
+    plan.forward(&queue, &buffer, &buffer).unwrap();
+    queue.finish().unwrap();
+
+    let report = plan.report();
+    for (direction, axes) in [("forward", &report.forward), ("inverse", &report.inverse)] {
+        let indices = axes.iter().map(|a| a.axis).collect::<Vec<_>>();
+        assert_eq!(indices, [0, 1], "{} axes", direction);
+        for axis in axes {
+            assert!(
+                !axis.bluestein,
+                "{} axis {} through Bluestein",
+                direction, axis.axis
+            );
+            assert!(
+                !axis.uploads.is_empty(),
+                "{} axis {} without kernels",
+                direction,
+                axis.axis
+            );
+            for upload in &axis.uploads {
+                assert!(!upload.radices.is_empty(), "{} : no radices", upload.name);
+                assert!(
+                    upload.source.as_ref().is_some_and(|s| !s.is_empty()),
+                    "{} : no source",
+                    upload.name
+                );
+                // Buffers are only known for the direction of the last launch.
+                let launched = direction == "forward";
+                assert_eq!(upload.reads.is_some(), launched, "{} reads", upload.name);
+                assert_eq!(upload.writes.is_some(), launched, "{} writes", upload.name);
+            }
+        }
+    }
+    assert!(report.sources().contains("__kernel"));
+}
+
+#[test]
+fn undersized_buffers_are_rejected() {
+    let Some((context, device, queue)) = device() else {
+        return;
+    };
+    let mut plan = Plan::builder(&[64]).build(&context, device).unwrap();
+    // f32 elements for a complex plan : half of the bytes it transforms.
+    let buffer = Buffer::<f32>::builder()
+        .queue(queue.clone())
+        .len(64)
+        .build()
+        .unwrap();
+    let error = plan.forward(&queue, &buffer, &buffer).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            LaunchError::BufferSize {
+                bytes: 256,
+                needed: 512,
+                ..
+            }
+        ),
+        "{:?}",
+        error
+    );
+}
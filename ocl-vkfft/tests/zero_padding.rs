@@ -0,0 +1,150 @@
+//! Frequency zero padding, as used for 3/2-rule dealiasing : the inverse must read the zero block
+//! as zeros whatever the buffer holds there, and the forward must not write it. Skipped when no
+//! OpenCL platform is available.
+
+mod common;
+
+use common::{complex_signal, device, max_error, narrow, read, reference, upload, widen};
+use num_complex::{Complex32, Complex64};
+use ocl_vkfft::{Plan, PlanBuilder};
+use std::ops::Range;
+
+/// Grid of a 16 x 16 spectrum padded to 24 x 24, modes 8 to 16 are the added ones.
+const M: usize = 24;
+const BLOCK: Range<usize> = 8..16;
+
+/// Whether element `k` of arrays of `size` has an index in `BLOCK` along one of the padded axes.
+fn in_block(k: usize, size: &[usize], axes: &[usize]) -> bool {
+    axes.iter().any(|&axis| {
+        let stride = size[..axis].iter().product::<usize>();
+        BLOCK.contains(&((k / stride) % size[axis]))
+    })
+}
+
+fn padded(size: &[usize], axes: &[usize]) -> PlanBuilder {
+    let mut builder = Plan::builder(size).frequency_zero_padding(true);
+    for &axis in axes {
+        builder = builder.zero_padding(axis, BLOCK);
+    }
+    builder
+}
+
+/// Inverse of a spectrum with garbage in the zero block, against the inverse of the same
+/// spectrum explicitly zeroed there.
+fn check_inverse(size: &[usize], axes: &[usize], batches: usize) {
+    let Some((context, device, queue)) = device() else {
+        return;
+    };
+    let len = size.iter().product::<usize>();
+    let spectrum = complex_signal(len * batches);
+    let mut full_size = size.to_vec();
+    full_size.push(batches);
+    let garbage = spectrum
+        .iter()
+        .enumerate()
+        .map(|(k, x)| {
+            if in_block(k, &full_size, axes) {
+                x * 1e3
+            } else {
+                *x
+            }
+        })
+        .collect::<Vec<_>>();
+    let zeroed = spectrum
+        .iter()
+        .enumerate()
+        .map(|(k, x)| {
+            if in_block(k, &full_size, axes) {
+                Complex64::new(0.0, 0.0)
+            } else {
+                *x
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut plan = padded(size, axes)
+        .batches(batches)
+        .build(&context, device)
+        .unwrap();
+    let buffer = upload(&queue, &narrow(&garbage));
+    plan.inverse(&queue, &buffer).unwrap();
+
+    let mut explicit = Plan::builder(size)
+        .batches(batches)
+        .build(&context, device)
+        .unwrap();
+    let expected = upload(&queue, &narrow(&zeroed));
+    explicit.inverse(&queue, &expected).unwrap();
+
+    let error = max_error(&widen(&read(&buffer)), &widen(&read(&expected)));
+    assert!(
+        error < 1e-5,
+        "{:?} x {} padded inverse : relative error {:e}",
+        size,
+        batches,
+        error
+    );
+}
+
+/// In place forward transform, against the full transform outside the zero block.
+fn check_forward(
+    size: &[usize],
+    axes: &[usize],
+    batches: usize,
+) -> Option<(Vec<Complex32>, Vec<Complex32>)> {
+    let (context, device, queue) = device()?;
+    let len = size.iter().product::<usize>();
+    let input = complex_signal(len * batches);
+    let mut full_size = size.to_vec();
+    full_size.push(batches);
+    let all_axes = (0..size.len()).collect::<Vec<_>>();
+    let expected = reference(&input, &full_size, &all_axes);
+
+    let mut plan = padded(size, axes)
+        .batches(batches)
+        .build(&context, device)
+        .unwrap();
+    let host = narrow(&input);
+    let buffer = upload(&queue, &host);
+    plan.forward(&queue, &buffer, &buffer).unwrap();
+    let output = read(&buffer);
+
+    let outside = |data: &[Complex64]| {
+        data.iter()
+            .enumerate()
+            .filter(|(k, _)| !in_block(*k, &full_size, axes))
+            .map(|(_, x)| *x)
+            .collect::<Vec<_>>()
+    };
+    let error = max_error(&outside(&widen(&output)), &outside(&expected));
+    assert!(
+        error < 1e-5,
+        "{:?} x {} padded forward : relative error {:e}",
+        size,
+        batches,
+        error
+    );
+    Some((host, output))
+}
+
+#[test]
+fn inverse_reads_zeros() {
+    check_inverse(&[M], &[0], 3);
+    // The layout of the 3/2-rule advection, padded along both axes.
+    check_inverse(&[M, M], &[0, 1], 1);
+    check_inverse(&[M, M], &[0, 1], 4);
+}
+
+#[test]
+fn forward_skips_zero_block() {
+    let Some((input, output)) = check_forward(&[M], &[0], 3) else {
+        return;
+    };
+    // Along a single axis nothing else writes the buffer, the block still holds the input.
+    for (k, (x, y)) in input.iter().zip(&output).enumerate() {
+        if BLOCK.contains(&(k % M)) {
+            assert_eq!(x, y, "element {} of the zero block was written", k);
+        }
+    }
+    check_forward(&[M, M], &[0, 1], 1);
+}
@@ -0,0 +1,296 @@
+use crate::utils;
+use anyhow::{anyhow, Result};
+use num::complex::{Complex32, Complex64};
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+use ocl::{Buffer, OclPrm};
+use ocl_vkfft::Plan;
+use std::f32::consts::PI;
+use std::fmt::Write;
+use std::time::Instant;
+
+/// Grid sizes and devices measured by `run`.
+pub struct BenchmarkConfig {
+    /// In increasing order, the sizes after one that does not fit on a device are skipped.
+    pub sizes: &'static [usize],
+    /// Timed repetitions of each measurement, after one warm-up, at least one.
+    pub repeats: u32,
+    /// Also times double precision FFTs, on devices with cl_khr_fp64.
+    pub double_precision: bool,
+    /// Every device of every platform, instead of the first one.
+    pub all_devices: bool,
+    /// Results are written there as JSON.
+    pub path: &'static str,
+}
+
+/// One measurement. `rate` is in GFLOP/s for FFTs, counting 5 n log2(n) flops per transform of
+/// n points, and in cells per second for solver steps.
+pub struct Record {
+    pub device: String,
+    pub kind: &'static str,
+    pub n: usize,
+    pub precision: &'static str,
+    pub ms: f64,
+    pub rate: f64,
+}
+
+/// Times the 2D FFT and the full periodic step for each size and device, prints a table and
+/// writes the results with the current commit, to compare them across commits.
+pub fn run(config: &BenchmarkConfig, src: &str) -> Result<()> {
+    if config.repeats == 0 {
+        return Err(anyhow!("The benchmark needs at least one timed repetition"));
+    }
+    let mut devices = Vec::new();
+    for platform in ocl::Platform::list() {
+        for device in ocl::Device::list_all(platform)? {
+            devices.push((platform, device));
+        }
+    }
+    if !config.all_devices {
+        devices.truncate(1);
+    }
+    if devices.is_empty() {
+        return Err(anyhow!("No OpenCL device to benchmark"));
+    }
+
+    let mut records = Vec::new();
+    for (platform, device) in devices {
+        let name = format!("{} ({})", device.name()?, platform.name()?);
+        let context = ocl::Context::builder()
+            .platform(platform)
+            .devices(device)
+            .build()?;
+        let queue = ocl::Queue::new(&context, device, None)?;
+        let program = ocl::Program::builder().src(src).build(&context)?;
+        let fp64 = match device.info(DeviceInfo::Extensions)? {
+            DeviceInfoResult::Extensions(extensions) => extensions.contains("cl_khr_fp64"),
+            _ => false,
+        };
+        for &n in config.sizes {
+            let measured = (|| -> Result<()> {
+                let ms = time_fft::<Complex32>(&context, device, &queue, n, false, config.repeats)?;
+                records.push(fft_record(&name, n, "f32", ms));
+                if config.double_precision && fp64 {
+                    let ms =
+                        time_fft::<Complex64>(&context, device, &queue, n, true, config.repeats)?;
+                    records.push(fft_record(&name, n, "f64", ms));
+                }
+                let ms = time_step(&program, &context, device, &queue, n, config.repeats)?;
+                records.push(Record {
+                    device: name.clone(),
+                    kind: "step",
+                    n,
+                    precision: "f32",
+                    ms,
+                    rate: (n * n) as f64 / (ms * 1e-3),
+                });
+                Ok(())
+            })();
+            if let Err(e) = measured {
+                println!("{} : N = {} skipped, {}", name, n, e);
+                break;
+            }
+        }
+    }
+
+    print_table(&records);
+    write_json(&records, config.path)?;
+    println!("Benchmark written to {}", config.path);
+    Ok(())
+}
+
+fn fft_record(device: &str, n: usize, precision: &'static str, ms: f64) -> Record {
+    let points = (n * n) as f64;
+    Record {
+        device: device.to_string(),
+        kind: "fft",
+        n,
+        precision,
+        ms,
+        rate: 5.0 * points * points.log2() / (ms * 1e-3) / 1e9,
+    }
+}
+
+/// Mean time in ms of `repeats` runs of `f`, after one warm-up run.
+fn time(queue: &ocl::Queue, repeats: u32, mut f: impl FnMut() -> Result<()>) -> Result<f64> {
+    f()?;
+    queue.finish()?;
+    let instant = Instant::now();
+    for _ in 0..repeats {
+        f()?;
+    }
+    queue.finish()?;
+    Ok(instant.elapsed().as_secs_f64() * 1e3 / repeats as f64)
+}
+
+/// In place forward FFT of an n x n grid.
+fn time_fft<T: OclPrm>(
+    context: &ocl::Context,
+    device: ocl::Device,
+    queue: &ocl::Queue,
+    n: usize,
+    double: bool,
+    repeats: u32,
+) -> Result<f64> {
+    let mut fft = Plan::builder(&[n, n])
+        .double_precision(double)
+        .build(context, device)?;
+    let buffer = Buffer::<T>::builder()
+        .queue(queue.clone())
+        .len(n * n)
+        .build()?;
+    time(queue, repeats, || Ok(fft.forward(queue, &buffer, &buffer)?))
+}
+
+/// The periodic step of the simulation : forward FFT of the vorticity, fused velocity kernel,
+/// batched inverse FFT of both components and semi-Lagrangian advection.
+fn time_step(
+    program: &ocl::Program,
+    context: &ocl::Context,
+    device: ocl::Device,
+    queue: &ocl::Queue,
+    n: usize,
+    repeats: u32,
+) -> Result<f64> {
+    let l = 2.0 * PI;
+    let w = utils::new_buffer(queue, n)?;
+    let wnew = utils::new_buffer(queue, n)?;
+    let what = utils::new_buffer(queue, n)?;
+    let psihat = utils::new_buffer(queue, n)?;
+    let u = Buffer::<Complex32>::builder()
+        .queue(queue.clone())
+        .len(2 * n * n)
+        .build()?;
+    let ux = u.create_sub_buffer(None, 0, n * n)?;
+    let uy = u.create_sub_buffer(None, n * n, n * n)?;
+    let init = utils::noise2d(n);
+    wnew.write(init.as_slice().ok_or(anyhow!("Non contiguous noise"))?)
+        .enq()?;
+
+    let mut fft = Plan::builder(&[n, n])
+        .out_of_place(true)
+        .build(context, device)?;
+    let mut fft_velocity = Plan::builder(&[n, n]).batches(2).build(context, device)?;
+    let kernel_velocity = unsafe {
+        ocl::Kernel::builder()
+            .program(program)
+            .queue(queue.clone())
+            .name("velocity_hat")
+            .global_work_size([n, n])
+            .disable_arg_type_check()
+            .arg(&what)
+            .arg(&psihat)
+            .arg(&ux)
+            .arg(&uy)
+            .arg(n as i32)
+            .arg(2.0 * PI / l)
+            .arg(0f32)
+            .arg(0i32)
+            .build()?
+    };
+    let kernel_advection = unsafe {
+        ocl::Kernel::builder()
+            .program(program)
+            .queue(queue.clone())
+            .name("advection")
+            .global_work_size([n, n])
+            .disable_arg_type_check()
+            .arg(&w)
+            .arg(&wnew)
+            .arg(&ux)
+            .arg(&uy)
+            .arg(n as i32)
+            .arg(l)
+            .arg(1e-3f32)
+            .build()?
+    };
+
+    time(queue, repeats, || {
+        wnew.copy(&w, None, None).enq()?;
+        fft.forward(queue, &w, &what)?;
+        unsafe {
+            kernel_velocity.enq()?;
+        }
+        fft_velocity.inverse(queue, &u)?;
+        unsafe {
+            kernel_advection.enq()?;
+        }
+        Ok(())
+    })
+}
+
+fn print_table(records: &[Record]) {
+    println!(
+        "{:<40} {:>5} {:>6} {:>4} {:>10} {:>14}",
+        "device", "kind", "N", "prec", "ms", "rate"
+    );
+    for r in records {
+        let rate = match r.kind {
+            "fft" => format!("{:.1} GFLOP/s", r.rate),
+            _ => format!("{:.3e} cells/s", r.rate),
+        };
+        println!(
+            "{:<40} {:>5} {:>6} {:>4} {:>10.3} {:>14}",
+            r.device, r.kind, r.n, r.precision, r.ms, rate
+        );
+    }
+}
+
+/// Short hash of the checked out commit, with a `-dirty` suffix for uncommitted changes.
+fn commit() -> String {
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    match git(&["rev-parse", "--short", "HEAD"]) {
+        Some(hash)
+            if git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty()) =>
+        {
+            hash + "-dirty"
+        }
+        Some(hash) => hash,
+        None => "unknown".to_string(),
+    }
+}
+
+fn write_json(records: &[Record], path: &str) -> Result<()> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let mut s = String::new();
+    writeln!(
+        s,
+        "{{\"commit\": \"{}\", \"timestamp\": {}, \"results\": [",
+        commit(),
+        timestamp
+    )?;
+    for (k, r) in records.iter().enumerate() {
+        write!(
+            s,
+            "  {{\"device\": \"{}\", \"kind\": \"{}\", \"n\": {}, \"precision\": \"{}\", \"ms\": {}, \"rate\": {}}}",
+            r.device.replace('\\', "\\\\").replace('"', "\\\""),
+            r.kind,
+            r.n,
+            r.precision,
+            json_number(r.ms),
+            json_number(r.rate)
+        )?;
+        s.push_str(if k + 1 < records.len() { ",\n" } else { "\n" });
+    }
+    s.push_str("]}\n");
+    std::fs::write(path, s)?;
+    Ok(())
+}
+
+/// JSON has no NaN nor infinities, e.g. the rate of a step timed at 0 ms.
+fn json_number(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
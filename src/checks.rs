@@ -0,0 +1,317 @@
+use anyhow::{anyhow, Result};
+use ndarray::Array2;
+use num::complex::Complex32;
+use ocl::{Buffer, Event};
+use ocl_vkfft::Plan;
+use std::f32::consts::PI;
+
+use crate::utils::{get_from_gpu, mode_power, new_buffer};
+
+/// Tolerances for the runtime self-checks. Every quantity is relative, see `Diagnostics`.
+#[derive(Clone, Copy, Debug)]
+pub struct Thresholds {
+    pub divergence: f32,
+    pub circulation: f32,
+    pub energy: f32,
+    /// Return an error instead of printing a warning when a threshold is exceeded.
+    pub abort: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostics {
+    /// ‖∇·u‖ / ‖ω‖, both as root mean squares over the grid.
+    pub divergence: f32,
+    /// Mean vorticity, i.e. circulation divided by the area of the box.
+    pub circulation: f32,
+    /// Root mean square vorticity, used to scale the circulation drift.
+    pub vorticity_rms: f32,
+    /// Kinetic energy per unit area, ½⟨|u|²⟩.
+    pub energy: f32,
+}
+
+pub struct SelfChecks {
+    thresholds: Thresholds,
+    ux_hat: Buffer<Complex32>,
+    uy_hat: Buffer<Complex32>,
+    kernel_div: ocl::Kernel,
+    initial: Option<Diagnostics>,
+    previous: Option<(u64, Diagnostics)>,
+}
+
+impl SelfChecks {
+    pub fn new(
+        program: &ocl::Program,
+        queue: &ocl::Queue,
+        n: usize,
+        l: f32,
+        thresholds: Thresholds,
+    ) -> Result<SelfChecks> {
+        let ux_hat = new_buffer(queue, n)?;
+        let uy_hat = new_buffer(queue, n)?;
+        let kernel_div = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("divergence_hat")
+                .global_work_size([n, n])
+                .disable_arg_type_check()
+                .arg(&ux_hat)
+                .arg(&uy_hat)
+                .arg(n as i32)
+                .arg(2.0 * PI / l)
+                .build()?
+        };
+        Ok(SelfChecks {
+            thresholds,
+            ux_hat,
+            uy_hat,
+            kernel_div,
+            initial: None,
+            previous: None,
+        })
+    }
+
+    /// Computes the diagnostics of the vorticity `w` and of the velocity `(ux, uy)` derived from it.
+    /// The velocity must already be back in physical space. Blocks until the queue is done.
+    pub fn measure(
+        &self,
+        fft: &mut Plan,
+        w: &Buffer<Complex32>,
+        ux: &Buffer<Complex32>,
+        uy: &Buffer<Complex32>,
+    ) -> Result<Diagnostics> {
+        let queue = self
+            .ux_hat
+            .default_queue()
+            .ok_or(anyhow!("No default queue"))?;
+        fft.forward(queue, ux, &self.ux_hat)?;
+        fft.forward(queue, uy, &self.uy_hat)?;
+        unsafe {
+            self.kernel_div.enq()?;
+        }
+
+        let divergence = mode_power(&get_from_gpu(&self.ux_hat)?).sum().sqrt();
+
+        let w = get_from_gpu(w)?.mapv(|x| x.re);
+        let circulation = mean(&w);
+        let vorticity_rms = mean(&w.mapv(|x| x * x)).sqrt();
+
+        let ux = get_from_gpu(ux)?.mapv(|x| x.re);
+        let uy = get_from_gpu(uy)?.mapv(|x| x.re);
+        let energy = 0.5 * (mean(&ux.mapv(|x| x * x)) + mean(&uy.mapv(|x| x * x)));
+
+        Ok(Diagnostics {
+            divergence: divergence / vorticity_rms,
+            circulation,
+            vorticity_rms,
+            energy,
+        })
+    }
+
+    /// Compares `diag` against the first measurement and the previous one,
+    /// warning or failing according to the thresholds.
+    pub fn check(&mut self, step: u64, diag: Diagnostics) -> Result<()> {
+        let initial = *self.initial.get_or_insert(diag);
+        let mut violations = vec![];
+
+        if diag.divergence > self.thresholds.divergence {
+            violations.push(format!("relative divergence {:e}", diag.divergence));
+        }
+        let drift = (diag.circulation - initial.circulation).abs() / initial.vorticity_rms;
+        if drift > self.thresholds.circulation {
+            violations.push(format!("mean vorticity drift {:e}", drift));
+        }
+        if let Some((prev_step, prev)) = self.previous {
+            let steps = (step - prev_step).max(1) as f32;
+            let change = (diag.energy - prev.energy).abs() / prev.energy / steps;
+            if change > self.thresholds.energy {
+                violations.push(format!("energy change per step {:e}", change));
+            }
+        }
+        self.previous = Some((step, diag));
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+        let message = format!(
+            "Self-check failed at step {} : {}",
+            step,
+            violations.join(", ")
+        );
+        if self.thresholds.abort {
+            return Err(anyhow!(message));
+        }
+        println!("Warning : {}", message);
+        Ok(())
+    }
+}
+
+fn mean(arr: &Array2<f32>) -> f32 {
+    arr.sum() / arr.len() as f32
+}
+
+/// Cheap guard against blow-up: non-finite values or explosive growth of max |ω|.
+///
+/// The status of a check is read back without blocking and inspected by the next check, so the
+/// queue never waits for the guard and a blow-up is reported one check late. Keeps device copies
+/// of the field being checked and of the last field that passed, to dump them when a check fails.
+pub struct BlowupGuard {
+    n: usize,
+    growth: f32,
+    w: Buffer<Complex32>,
+    checked: Buffer<Complex32>,
+    last_good: Buffer<Complex32>,
+    last_good_step: u64,
+    status: Buffer<i32>,
+    reset: Buffer<i32>,
+    // Written by the read of the pending check, must not be touched before its event completes.
+    host_status: Vec<i32>,
+    pending: Option<(u64, Event)>,
+    kernel_check: ocl::Kernel,
+    initial_max: Option<f32>,
+}
+
+pub enum Blowup {
+    NonFinite { i: usize, j: usize },
+    Growth { i: usize, j: usize, max: f32 },
+}
+
+impl BlowupGuard {
+    pub fn new(
+        program: &ocl::Program,
+        queue: &ocl::Queue,
+        w: &Buffer<Complex32>,
+        n: usize,
+        growth: f32,
+    ) -> Result<BlowupGuard> {
+        let status = Buffer::<i32>::builder()
+            .queue(queue.clone())
+            .len(2)
+            .build()?;
+        let reset = Buffer::<i32>::builder()
+            .queue(queue.clone())
+            .len(2)
+            .copy_host_slice(&[i32::MAX, 0])
+            .build()?;
+        let kernel_check = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("blowup_check")
+                .global_work_size(usize::min(n * n, 1 << 16))
+                .disable_arg_type_check()
+                .arg(w)
+                .arg(&status)
+                .arg((n * n) as i32)
+                .build()?
+        };
+        let last_good = new_buffer(queue, n)?;
+        w.copy(&last_good, None, None).enq()?;
+        Ok(BlowupGuard {
+            n,
+            growth,
+            w: w.clone(),
+            checked: new_buffer(queue, n)?,
+            last_good,
+            last_good_step: 0,
+            status,
+            reset,
+            host_status: vec![i32::MAX, 0],
+            pending: None,
+            kernel_check,
+            initial_max: None,
+        })
+    }
+
+    /// Last field that passed a check, and its step.
+    pub fn last_good(&self) -> (u64, &Buffer<Complex32>) {
+        (self.last_good_step, &self.last_good)
+    }
+
+    /// Field of the last check, the first bad one once a blow-up is reported.
+    pub fn first_bad(&self) -> &Buffer<Complex32> {
+        &self.checked
+    }
+
+    /// Enqueues the check of the watched buffer, holding the field of `step`, and returns the
+    /// outcome of the previous check with its step. Only 8 bytes are read back, and the field is
+    /// copied only on the checked steps. Growth is measured against the first nonzero max |ω|.
+    pub fn check(&mut self, step: u64) -> Result<Option<(u64, Blowup)>> {
+        if let Some(blowup) = self.resolve()? {
+            return Ok(Some(blowup));
+        }
+        self.reset.copy(&self.status, None, None).enq()?;
+        unsafe {
+            self.kernel_check.enq()?;
+        }
+        self.w.copy(&self.checked, None, None).enq()?;
+        let mut read = Event::empty();
+        unsafe {
+            self.status
+                .read(&mut self.host_status[..])
+                .block(false)
+                .enew(&mut read)
+                .enq()?;
+        }
+        self.status
+            .default_queue()
+            .ok_or(anyhow!("Status buffer without a queue"))?
+            .flush()?;
+        self.pending = Some((step, read));
+        Ok(None)
+    }
+
+    /// Waits for the pending check, if any, and returns its outcome with its step.
+    pub fn resolve(&mut self) -> Result<Option<(u64, Blowup)>> {
+        let Some((step, read)) = self.pending.take() else {
+            return Ok(None);
+        };
+        read.wait_for()?;
+        if self.host_status[0] != i32::MAX {
+            let k = self.host_status[0] as usize;
+            let blowup = Blowup::NonFinite {
+                i: k / self.n,
+                j: k % self.n,
+            };
+            return Ok(Some((step, blowup)));
+        }
+        let max = f32::from_bits(self.host_status[1] as u32);
+        // A zero field, e.g. at rest before forcing, cannot be a baseline for growth.
+        if self.initial_max.is_none() && max > 0.0 {
+            self.initial_max = Some(max);
+        }
+        if let Some(initial_max) = self.initial_max {
+            if max > self.growth * initial_max {
+                let w = get_from_gpu(&self.checked)?;
+                let ((i, j), _) = w
+                    .indexed_iter()
+                    .max_by(|a, b| a.1.re.abs().total_cmp(&b.1.re.abs()))
+                    .ok_or(anyhow!("Empty buffer"))?;
+                return Ok(Some((step, Blowup::Growth { i, j, max })));
+            }
+        }
+        std::mem::swap(&mut self.checked, &mut self.last_good);
+        self.last_good_step = step;
+        Ok(None)
+    }
+}
+
+/// The pending read writes into `host_status`, which must outlive it.
+impl Drop for BlowupGuard {
+    fn drop(&mut self) {
+        if let Some((_, read)) = self.pending.take() {
+            let _ = read.wait_for();
+        }
+    }
+}
+
+impl std::fmt::Display for Blowup {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Blowup::NonFinite { i, j } => write!(f, "non-finite vorticity at ({}, {})", i, j),
+            Blowup::Growth { i, j, max } => {
+                write!(f, "explosive growth, max |w| = {:e} at ({}, {})", max, i, j)
+            }
+        }
+    }
+}
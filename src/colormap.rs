@@ -0,0 +1,333 @@
+use anyhow::{anyhow, Result};
+use colorgrad::Gradient;
+use image::{ImageBuffer, Rgb};
+use ndarray::Array2;
+use num::complex::Complex32;
+use ocl::Buffer;
+
+/// Entries of the lookup table sampled from the gradient for the device colormap.
+const LUT_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Colormap {
+    /// The original red-white-blue Catmull-Rom gradient.
+    RedWhiteBlue,
+    RdBu,
+    Spectral,
+    Viridis,
+    Inferno,
+    Magma,
+    Plasma,
+    Cividis,
+    Turbo,
+    Greys,
+}
+
+/// How the color limits are computed from a frame.
+#[derive(Clone, Copy, Debug)]
+pub enum Limits {
+    /// [-m, m] with m the largest |value|, zero is always mapped to the middle of the colormap.
+    Symmetric,
+    /// [min, max] of the values.
+    Asymmetric,
+    /// [q(p), q(100 - p)] with q the percentiles of the values, robust to a few extreme cells.
+    Percentile(f32),
+    /// [-q, q] with q the (100 - p)-th percentile of |value|.
+    SymmetricPercentile(f32),
+    /// Fixed limits, whatever the normalization mode.
+    Fixed(f32, f32),
+}
+
+/// How the limits of successive frames are combined.
+#[derive(Clone, Copy, Debug)]
+pub enum Normalization {
+    /// Limits of the current frame only, colors flicker in videos.
+    PerFrame,
+    /// Union of the limits of all the frames seen so far.
+    Running,
+    /// Limits of the first frame, kept for the whole run.
+    Global,
+}
+
+/// Colormap and normalization state, shared by every output that should use consistent colors.
+pub struct ColorScale {
+    pub limits: Limits,
+    pub normalization: Normalization,
+    gradient: Box<dyn Gradient + Send>,
+    range: Option<(f32, f32)>,
+}
+
+impl Colormap {
+    pub fn gradient(&self) -> Result<Box<dyn Gradient + Send>> {
+        use colorgrad::preset;
+        let gradient: Box<dyn Gradient + Send> = match self {
+            Colormap::RedWhiteBlue => Box::new(
+                colorgrad::GradientBuilder::new()
+                    .html_colors(&["red", "white", "blue"])
+                    .build::<colorgrad::CatmullRomGradient>()?,
+            ),
+            Colormap::RdBu => Box::new(preset::rd_bu()),
+            Colormap::Spectral => Box::new(preset::spectral()),
+            Colormap::Viridis => Box::new(preset::viridis()),
+            Colormap::Inferno => Box::new(preset::inferno()),
+            Colormap::Magma => Box::new(preset::magma()),
+            Colormap::Plasma => Box::new(preset::plasma()),
+            Colormap::Cividis => Box::new(preset::cividis()),
+            Colormap::Turbo => Box::new(preset::turbo()),
+            Colormap::Greys => Box::new(preset::greys()),
+        };
+        Ok(gradient)
+    }
+}
+
+impl ColorScale {
+    pub fn new(
+        colormap: Colormap,
+        limits: Limits,
+        normalization: Normalization,
+    ) -> Result<ColorScale> {
+        Ok(ColorScale {
+            limits,
+            normalization,
+            gradient: colormap.gradient()?,
+            range: None,
+        })
+    }
+
+    /// The original behaviour : red-white-blue, symmetric, normalized per frame.
+    pub fn per_frame() -> Result<ColorScale> {
+        ColorScale::new(
+            Colormap::RedWhiteBlue,
+            Limits::Symmetric,
+            Normalization::PerFrame,
+        )
+    }
+
+    /// Limits used for the last frame, if any.
+    pub fn range(&self) -> Option<(f32, f32)> {
+        self.range
+    }
+
+    /// Overrides the limits of the last frame, for frames colormapped elsewhere.
+    pub fn set_range(&mut self, range: (f32, f32)) {
+        self.range = Some(range);
+    }
+
+    /// `size` colors evenly sampled along the gradient, as packed RGB8.
+    pub fn lut(&self, size: usize) -> Vec<u8> {
+        (0..size)
+            .flat_map(|k| self.color(k as f32 / (size - 1) as f32).0)
+            .collect()
+    }
+
+    pub fn gradient(&self) -> &dyn Gradient {
+        self.gradient.as_ref()
+    }
+
+    /// Updates the limits with a new frame and returns the ones to use for it.
+    pub fn update(&mut self, data: &Array2<f32>) -> (f32, f32) {
+        let range = match (self.normalization, self.range) {
+            (Normalization::Global, Some(range)) => range,
+            (Normalization::Running, Some((lo, hi))) => {
+                let (l, h) = frame_limits(self.limits, data);
+                (lo.min(l), hi.max(h))
+            }
+            _ => frame_limits(self.limits, data),
+        };
+        self.range = Some(range);
+        range
+    }
+
+    /// Position in [0, 1] of `x` in the colormap, for the limits `(lo, hi)`.
+    pub fn normalize(x: f32, (lo, hi): (f32, f32)) -> f32 {
+        if hi > lo {
+            ((x - lo) / (hi - lo)).clamp(0.0, 1.0)
+        } else {
+            0.5
+        }
+    }
+
+    pub fn color(&self, t: f32) -> Rgb<u8> {
+        let v = self.gradient.at(t).to_rgba8();
+        Rgb([v[0], v[1], v[2]])
+    }
+
+    pub fn image(&mut self, data: &Array2<f32>) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let n = data.len_of(ndarray::Axis(0));
+        let range = self.update(data);
+        image::ImageBuffer::from_fn(n as u32, n as u32, |i, j| {
+            self.color(ColorScale::normalize(data[[i as usize, j as usize]], range))
+        })
+    }
+}
+
+/// Normalizes and colormaps fields on the device, so that only packed RGB8 is read back.
+/// Follows the limits and normalization of a `ColorScale`, except percentile limits.
+pub struct DeviceColorScale {
+    limits: Limits,
+    normalization: Normalization,
+    rgb: Buffer<u8>,
+    // Ordered bits of the current limits, see `ordered_bits`.
+    range: Buffer<i32>,
+    empty_range: Buffer<i32>,
+    kernel_range: ocl::Kernel,
+    kernel_rgb: ocl::Kernel,
+    frames: u64,
+}
+
+impl DeviceColorScale {
+    pub fn new(
+        program: &ocl::Program,
+        queue: &ocl::Queue,
+        n: usize,
+        scale: &ColorScale,
+    ) -> Result<DeviceColorScale> {
+        let initial = match scale.limits {
+            Limits::Percentile(_) | Limits::SymmetricPercentile(_) => {
+                return Err(anyhow!(
+                    "Percentile limits need the whole frame on the CPU, use another limit for the video"
+                ))
+            }
+            Limits::Fixed(lo, hi) => [ordered_bits(lo), ordered_bits(hi)],
+            _ => [i32::MAX, i32::MIN],
+        };
+        let rgb = Buffer::<u8>::builder()
+            .queue(queue.clone())
+            .len(3 * n * n)
+            .build()?;
+        let range = Buffer::<i32>::builder()
+            .queue(queue.clone())
+            .len(2)
+            .copy_host_slice(&initial)
+            .build()?;
+        let empty_range = Buffer::<i32>::builder()
+            .queue(queue.clone())
+            .len(2)
+            .copy_host_slice(&initial)
+            .build()?;
+        let lut = Buffer::<u8>::builder()
+            .queue(queue.clone())
+            .len(3 * LUT_SIZE)
+            .copy_host_slice(&scale.lut(LUT_SIZE))
+            .build()?;
+        let kernel_range = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("field_range")
+                .global_work_size(usize::min(n * n, 1 << 16))
+                .disable_arg_type_check()
+                .arg(None::<&Buffer<Complex32>>)
+                .arg(&range)
+                .arg((n * n) as i32)
+                .arg(matches!(scale.limits, Limits::Symmetric) as i32)
+                .build()?
+        };
+        let kernel_rgb = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("colormap_rgb")
+                .global_work_size([n, n])
+                .disable_arg_type_check()
+                .arg(None::<&Buffer<Complex32>>)
+                .arg(&rgb)
+                .arg(&lut)
+                .arg(LUT_SIZE as i32)
+                .arg(&range)
+                .arg(n as i32)
+                .build()?
+        };
+        Ok(DeviceColorScale {
+            limits: scale.limits,
+            normalization: scale.normalization,
+            rgb,
+            range,
+            empty_range,
+            kernel_range,
+            kernel_rgb,
+            frames: 0,
+        })
+    }
+
+    /// Enqueues the update of the limits with `input` and its colormapping.
+    /// Returns the RGB8 image and the encoded limits used for it, see `decode_range`.
+    pub fn apply(&mut self, input: &Buffer<Complex32>) -> Result<(&Buffer<u8>, &Buffer<i32>)> {
+        let update = match (self.limits, self.normalization) {
+            (Limits::Fixed(..), _) => false,
+            (_, Normalization::Global) => self.frames == 0,
+            (_, Normalization::PerFrame) => {
+                self.empty_range.copy(&self.range, None, None).enq()?;
+                true
+            }
+            (_, Normalization::Running) => true,
+        };
+        unsafe {
+            if update {
+                self.kernel_range.set_arg(0, input)?;
+                self.kernel_range.enq()?;
+            }
+            self.kernel_rgb.set_arg(0, input)?;
+            self.kernel_rgb.enq()?;
+        }
+        self.frames += 1;
+        Ok((&self.rgb, &self.range))
+    }
+}
+
+/// Maps floats to ints of the same order, as in kernels.cl.
+fn ordered_bits(x: f32) -> i32 {
+    flip_negative(x.to_bits() as i32)
+}
+
+fn flip_negative(b: i32) -> i32 {
+    if b < 0 {
+        b ^ 0x7fffffff
+    } else {
+        b
+    }
+}
+
+/// Limits read back from `DeviceColorScale::apply`, `None` if no value was seen.
+pub fn decode_range(bits: [i32; 2]) -> Option<(f32, f32)> {
+    let [lo, hi] = bits.map(|b| f32::from_bits(flip_negative(b) as u32));
+    if lo <= hi {
+        Some((lo, hi))
+    } else {
+        None
+    }
+}
+
+fn frame_limits(limits: Limits, data: &Array2<f32>) -> (f32, f32) {
+    match limits {
+        Limits::Symmetric => {
+            let m = data.iter().map(|x| x.abs()).fold(0.0, f32::max);
+            (-m, m)
+        }
+        Limits::Asymmetric => data
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &x| {
+                (lo.min(x), hi.max(x))
+            }),
+        Limits::Percentile(p) => {
+            let mut values = data.iter().cloned().collect::<Vec<f32>>();
+            (
+                percentile(&mut values, p),
+                percentile(&mut values, 100.0 - p),
+            )
+        }
+        Limits::SymmetricPercentile(p) => {
+            let mut values = data.iter().map(|x| x.abs()).collect::<Vec<f32>>();
+            let m = percentile(&mut values, 100.0 - p);
+            (-m, m)
+        }
+        Limits::Fixed(lo, hi) => (lo, hi),
+    }
+}
+
+/// Nearest-rank percentile, reorders `values`.
+fn percentile(values: &mut [f32], p: f32) -> f32 {
+    let k = ((p / 100.0) * (values.len() - 1) as f32).round() as usize;
+    let (_, x, _) = values.select_nth_unstable_by(k.min(values.len() - 1), f32::total_cmp);
+    *x
+}
@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Result};
+use num::complex::Complex32;
+use ocl::Buffer;
+use ocl_vkfft::Plan;
+use std::f32::consts::PI;
+
+use crate::utils::new_buffer;
+
+/// Scalar fields that can be rendered, derived from the vorticity of the current step or carried
+/// by the flow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Vorticity,
+    Streamfunction,
+    VelocityMagnitude,
+    VelocityX,
+    VelocityY,
+    /// W = sn² + ss² - ω², negative where rotation dominates strain.
+    OkuboWeiss,
+    /// Passive scalar of index k in `Sources::scalars`.
+    Scalar(usize),
+}
+
+/// Source buffers of the solver the fields are derived from.
+pub struct Sources {
+    pub w: Buffer<Complex32>,
+    pub psihat: Buffer<Complex32>,
+    pub ux: Buffer<Complex32>,
+    pub uy: Buffer<Complex32>,
+    /// Name and buffer of each passive scalar, see `PassiveScalars::fields`.
+    pub scalars: Vec<(&'static str, Buffer<Complex32>)>,
+}
+
+/// Computes the derived fields on the device, into the real part of a render buffer.
+pub struct FieldRenderer {
+    sources: Sources,
+    render: Buffer<Complex32>,
+    kernel_magnitude: ocl::Kernel,
+    // Only allocated when the Okubo–Weiss parameter is requested.
+    strain: Option<Strain>,
+}
+
+struct Strain {
+    sn: Buffer<Complex32>,
+    ss: Buffer<Complex32>,
+    kernel_strain: ocl::Kernel,
+    kernel_ow: ocl::Kernel,
+}
+
+impl FieldRenderer {
+    /// `fields` lists every field that will be requested, to allocate only the needed buffers.
+    pub fn new(
+        program: &ocl::Program,
+        queue: &ocl::Queue,
+        n: usize,
+        l: f32,
+        sources: Sources,
+        fields: &[Field],
+    ) -> Result<FieldRenderer> {
+        for field in fields {
+            if let Field::Scalar(k) = field {
+                if *k >= sources.scalars.len() {
+                    return Err(anyhow!(
+                        "Field::Scalar({}) requested with {} passive scalars",
+                        k,
+                        sources.scalars.len()
+                    ));
+                }
+            }
+        }
+        let render = new_buffer(queue, n)?;
+        let kernel_magnitude = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("magnitude")
+                .global_work_size([n, n])
+                .disable_arg_type_check()
+                .arg(&sources.ux)
+                .arg(&sources.uy)
+                .arg(&render)
+                .arg(n as i32)
+                .build()?
+        };
+
+        let mut strain = None;
+        if fields.contains(&Field::OkuboWeiss) {
+            let sn = new_buffer(queue, n)?;
+            let ss = new_buffer(queue, n)?;
+            let kernel_strain = unsafe {
+                ocl::Kernel::builder()
+                    .program(program)
+                    .queue(queue.clone())
+                    .name("strain_hat")
+                    .global_work_size([n, n])
+                    .disable_arg_type_check()
+                    .arg(&sources.psihat)
+                    .arg(&sn)
+                    .arg(&ss)
+                    .arg(n as i32)
+                    .arg(2.0 * PI / l)
+                    .build()?
+            };
+            let kernel_ow = unsafe {
+                ocl::Kernel::builder()
+                    .program(program)
+                    .queue(queue.clone())
+                    .name("okubo_weiss")
+                    .global_work_size([n, n])
+                    .disable_arg_type_check()
+                    .arg(&sn)
+                    .arg(&ss)
+                    .arg(&sources.w)
+                    .arg(&render)
+                    .arg(n as i32)
+                    .build()?
+            };
+            strain = Some(Strain {
+                sn,
+                ss,
+                kernel_strain,
+                kernel_ow,
+            });
+        }
+
+        Ok(FieldRenderer {
+            sources,
+            render,
+            kernel_magnitude,
+            strain,
+        })
+    }
+
+    /// Used in file names and annotations.
+    pub fn name(&self, field: Field) -> &'static str {
+        match field {
+            Field::Vorticity => "vorticity",
+            Field::Streamfunction => "streamfunction",
+            Field::VelocityMagnitude => "velocity_magnitude",
+            Field::VelocityX => "velocity_x",
+            Field::VelocityY => "velocity_y",
+            Field::OkuboWeiss => "okubo_weiss",
+            Field::Scalar(k) => self.sources.scalars[k].0,
+        }
+    }
+
+    /// Enqueues the computation of `field` and returns the buffer holding it in its real part.
+    /// The sources must be up to date : ψ̂ and the velocity of the current vorticity.
+    pub fn compute(&self, fft: &mut Plan, field: Field) -> Result<&Buffer<Complex32>> {
+        match field {
+            Field::Vorticity => Ok(&self.sources.w),
+            Field::VelocityX => Ok(&self.sources.ux),
+            Field::VelocityY => Ok(&self.sources.uy),
+            Field::Scalar(k) => Ok(&self.sources.scalars[k].1),
+            Field::VelocityMagnitude => {
+                unsafe {
+                    self.kernel_magnitude.enq()?;
+                }
+                Ok(&self.render)
+            }
+            Field::Streamfunction => {
+                self.sources.psihat.copy(&self.render, None, None).enq()?;
+                inverse_fft(fft, &self.render)?;
+                Ok(&self.render)
+            }
+            Field::OkuboWeiss => {
+                let strain = self
+                    .strain
+                    .as_ref()
+                    .ok_or(anyhow!("Okubo-Weiss was not requested at creation"))?;
+                unsafe {
+                    strain.kernel_strain.enq()?;
+                }
+                inverse_fft(fft, &strain.sn)?;
+                inverse_fft(fft, &strain.ss)?;
+                unsafe {
+                    strain.kernel_ow.enq()?;
+                }
+                Ok(&self.render)
+            }
+        }
+    }
+}
+
+/// In place inverse FFT on the default queue of `buffer`.
+fn inverse_fft(fft: &mut Plan, buffer: &Buffer<Complex32>) -> Result<()> {
+    let queue = buffer.default_queue().ok_or(anyhow!("No default queue"))?;
+    fft.inverse(queue, buffer)?;
+    Ok(())
+}
+
+/// Box-averages a field on the device, to encode smaller frames and read back less data.
+pub struct Downsampler {
+    out: Buffer<Complex32>,
+    kernel: ocl::Kernel,
+}
+
+impl Downsampler {
+    pub fn new(
+        program: &ocl::Program,
+        queue: &ocl::Queue,
+        n: usize,
+        m: usize,
+    ) -> Result<Downsampler> {
+        let out = new_buffer(queue, m)?;
+        let kernel = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("downsample")
+                .global_work_size([m, m])
+                .disable_arg_type_check()
+                .arg(None::<&Buffer<Complex32>>)
+                .arg(&out)
+                .arg(n as i32)
+                .arg((n / m) as i32)
+                .build()?
+        };
+        Ok(Downsampler { out, kernel })
+    }
+
+    /// Enqueues the average of `input` and returns the buffer holding it.
+    pub fn apply(&self, input: &Buffer<Complex32>) -> Result<&Buffer<Complex32>> {
+        self.kernel.set_arg(0, input)?;
+        unsafe {
+            self.kernel.enq()?;
+        }
+        Ok(&self.out)
+    }
+}
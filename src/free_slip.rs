@@ -0,0 +1,158 @@
+use anyhow::Result;
+use num::complex::Complex32;
+use ocl::Buffer;
+use ocl_vkfft::{Plan, Transform};
+use std::f32::consts::PI;
+
+/// Boundary conditions of the box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boundary {
+    Periodic,
+    /// Impermeable free-slip walls : ψ = 0 and ω = 0 on the boundary, solved with sine and
+    /// cosine series on a cell centered grid.
+    FreeSlip,
+}
+
+/// Velocity of the vorticity in a box with free-slip walls.
+///
+/// ω and ψ are sine series along both axes, ux = ∂_jψ is a cosine series along j and
+/// uy = -∂_iψ along i, each transformed back one axis at a time.
+pub struct FreeSlip {
+    w_hat: Buffer<f32>,
+    psi_hat: Buffer<f32>,
+    ux_hat: Buffer<f32>,
+    uy_hat: Buffer<f32>,
+    dst: Plan,
+    // Along j, the contiguous axis, or along i only.
+    dst_j: Plan,
+    dct_j: Plan,
+    dst_i: Plan,
+    dct_i: Plan,
+    kernel_real: ocl::Kernel,
+    kernel_poisson: ocl::Kernel,
+    kernel_ux: ocl::Kernel,
+    kernel_uy: ocl::Kernel,
+}
+
+impl FreeSlip {
+    /// Reads the vorticity in the real part of `w` and writes the velocity in the real parts of
+    /// `ux` and `uy`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        program: &ocl::Program,
+        queue: &ocl::Queue,
+        context: &ocl::Context,
+        device: ocl::Device,
+        n: usize,
+        l: f32,
+        w: &Buffer<Complex32>,
+        ux: &Buffer<Complex32>,
+        uy: &Buffer<Complex32>,
+    ) -> Result<FreeSlip> {
+        let real = || {
+            Buffer::<f32>::builder()
+                .queue(queue.clone())
+                .len(n * n)
+                .build()
+        };
+        let w_hat = real()?;
+        let psi_hat = real()?;
+        let ux_hat = real()?;
+        let uy_hat = real()?;
+
+        let plan = |transform, omit: Option<usize>| {
+            let mut builder = Plan::builder(&[n, n]).transform(transform);
+            if let Some(axis) = omit {
+                builder = builder.omit_dimension(axis);
+            }
+            builder.build(context, device)
+        };
+        // VkFFT axis 0 is the contiguous axis j, axis 1 is i.
+        let dst = plan(Transform::Dst(2), None)?;
+        let dst_j = plan(Transform::Dst(2), Some(1))?;
+        let dct_j = plan(Transform::Dct(2), Some(1))?;
+        let dst_i = plan(Transform::Dst(2), Some(0))?;
+        let dct_i = plan(Transform::Dct(2), Some(0))?;
+
+        let kernel_real = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("real_part")
+                .global_work_size(n * n)
+                .disable_arg_type_check()
+                .arg(w)
+                .arg(&w_hat)
+                .build()?
+        };
+        let kernel_poisson = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("inv_mlap_free_slip")
+                .global_work_size([n, n])
+                .disable_arg_type_check()
+                .arg(&w_hat)
+                .arg(&psi_hat)
+                .arg(&ux_hat)
+                .arg(&uy_hat)
+                .arg(n as i32)
+                .arg(PI / l)
+                .build()?
+        };
+        let to_complex = |input: &Buffer<f32>, output: &Buffer<Complex32>| unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("to_complex")
+                .global_work_size(n * n)
+                .disable_arg_type_check()
+                .arg(input)
+                .arg(output)
+                .build()
+        };
+        let kernel_ux = to_complex(&ux_hat, ux)?;
+        let kernel_uy = to_complex(&uy_hat, uy)?;
+
+        Ok(FreeSlip {
+            w_hat,
+            psi_hat,
+            ux_hat,
+            uy_hat,
+            dst,
+            dst_j,
+            dct_j,
+            dst_i,
+            dct_i,
+            kernel_real,
+            kernel_poisson,
+            kernel_ux,
+            kernel_uy,
+        })
+    }
+
+    /// Enqueues the computation of the velocity from the current vorticity.
+    pub fn velocity(&mut self, queue: &ocl::Queue) -> Result<()> {
+        unsafe {
+            self.kernel_real.enq()?;
+        }
+        self.dst.forward(queue, &self.w_hat, &self.w_hat)?;
+        unsafe {
+            self.kernel_poisson.enq()?;
+        }
+        self.dct_j.inverse(queue, &self.ux_hat)?;
+        self.dst_i.inverse(queue, &self.ux_hat)?;
+        self.dst_j.inverse(queue, &self.uy_hat)?;
+        self.dct_i.inverse(queue, &self.uy_hat)?;
+        unsafe {
+            self.kernel_ux.enq()?;
+            self.kernel_uy.enq()?;
+        }
+        Ok(())
+    }
+
+    /// DST-II coefficients of ψ, of mode (i + 1, j + 1), after `velocity`.
+    pub fn psi_hat(&self) -> &Buffer<f32> {
+        &self.psi_hat
+    }
+}
@@ -32,6 +32,87 @@ __kernel void inv_mlap(__global float2* buffer_in, __global float2* buffer_out,
     buffer_out[i*N +j].x = re / s;
     buffer_out[i*N +j].y = im / s;
 }
+// inv_mlap, diff_y and mdiff_x fused in one pass over w_hat, scalar is 2*pi/L.
+// If nu_dt > 0, w_hat is first damped in place by exp(-nu dt k^2).
+// If dealias, the velocity and psi_hat are truncated to |k| < N/3 along each axis (2/3 rule).
+__kernel void velocity_hat(__global float2* w_hat, __global float2* psi_hat, __global float2* ux_hat, __global float2* uy_hat, int N, float scalar, float nu_dt, int dealias) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    int ki = i - N * (2*i >= N);
+    int kj = j - N * (2*j >= N);
+    float freqi = scalar * (float)ki;
+    float freqj = scalar * (float)kj;
+    float k2 = freqi*freqi + freqj*freqj;
+    float2 w = w_hat[i*N +j];
+    if (nu_dt > 0) {
+        w *= exp(-nu_dt * k2);
+        w_hat[i*N +j] = w;
+    }
+    if (dealias && (3*abs(ki) >= N || 3*abs(kj) >= N)) {
+        w = (float2)(0, 0);
+    }
+    float2 p = w / (k2 + ((i==0) && (j==0)));
+    psi_hat[i*N +j] = p;
+    ux_hat[i*N +j] = (float2)(-p.y * freqj, p.x * freqj);
+    uy_hat[i*N +j] = (float2)( p.y * freqi, -p.x * freqi);
+}
+// div in spectral space, written over ux_hat, scalar is 2*pi/L
+__kernel void divergence_hat(__global float2* ux_hat, __global float2* uy_hat, int N, float scalar) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    float freqi = scalar * ((float)i - (float)N * (2*i >= N));
+    float freqj = scalar * ((float)j - (float)N * (2*j >= N));
+    float2 a = ux_hat[i*N +j];
+    float2 b = uy_hat[i*N +j];
+    ux_hat[i*N +j].x = -freqi * a.y - freqj * b.y;
+    ux_hat[i*N +j].y =  freqi * a.x + freqj * b.x;
+}
+// Strain rates of u = (dxu, dyu) derived from psi_hat : sn = di ui - dj uj, ss = di uj + dj ui
+__kernel void strain_hat(__global float2* psi_hat, __global float2* sn_hat, __global float2* ss_hat, int N, float scalar) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    float freqi = scalar * ((float)i - (float)N * (2*i >= N));
+    float freqj = scalar * ((float)j - (float)N * (2*j >= N));
+    float2 p = psi_hat[i*N +j];
+    sn_hat[i*N +j] = -2.0f * freqi * freqj * p;
+    ss_hat[i*N +j] = (freqi*freqi - freqj*freqj) * p;
+}
+__kernel void okubo_weiss(__global float2* sn, __global float2* ss, __global float2* w, __global float2* out, int N) {
+    int k = get_global_id(0)*N + get_global_id(1);
+    out[k].x = sn[k].x*sn[k].x + ss[k].x*ss[k].x - w[k].x*w[k].x;
+    out[k].y = 0;
+}
+__kernel void magnitude(__global float2* ux, __global float2* uy, __global float2* out, int N) {
+    int k = get_global_id(0)*N + get_global_id(1);
+    out[k].x = hypot(ux[k].x, uy[k].x);
+    out[k].y = 0;
+}
+// Box average over factor x factor blocks, out is (N/factor) x (N/factor)
+__kernel void downsample(__global float2* in, __global float2* out, int N, int factor) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    float s = 0;
+    for (int a = 0; a < factor; a++) {
+        for (int b = 0; b < factor; b++) {
+            s += in[(i*factor + a)*N + j*factor + b].x;
+        }
+    }
+    out[i*(N/factor) + j].x = s / (float)(factor*factor);
+    out[i*(N/factor) + j].y = 0;
+}
+// status[0] : smallest index of a non-finite value, status[1] : bits of the largest |w|.
+// Non-negative floats compare like their bits as ints, and inf/NaN compare above all finite values.
+__kernel void blowup_check(__global float2* w, __global int* status, int len) {
+    int bad = INT_MAX;
+    int m = 0;
+    for (int k = get_global_id(0); k < len; k += get_global_size(0)) {
+        int bits = as_int(fabs(w[k].x));
+        if (bits >= 0x7f800000) bad = min(bad, k);
+        m = max(m, bits);
+    }
+    atomic_min(&status[0], bad);
+    atomic_max(&status[1], m);
+}
 __kernel void advection(__global float2* w_in, __global float2* w_out, __global float2* ux, __global float2* uy, int N, float L, float dt) {
     int i = get_global_id(0);
     int j = get_global_id(1);
@@ -56,3 +137,120 @@ __kernel void advection(__global float2* w_in, __global float2* w_out, __global
     w_out[i*N +j].x = s;
     w_out[i*N +j].y = 0;
 }
+// Maps floats to ints of the same order, the map is its own inverse.
+int ordered_bits(float x) {
+    int b = as_int(x);
+    return b < 0 ? b ^ 0x7fffffff : b;
+}
+float from_ordered_bits(int b) {
+    return as_float(b < 0 ? b ^ 0x7fffffff : b);
+}
+// range[0], range[1] : ordered bits of the min and max of the real part, of -|x| and |x| if symmetric.
+__kernel void field_range(__global float2* in, __global int* range, int len, int symmetric) {
+    int lo = INT_MAX;
+    int hi = INT_MIN;
+    for (int k = get_global_id(0); k < len; k += get_global_size(0)) {
+        float x = symmetric ? fabs(in[k].x) : in[k].x;
+        lo = min(lo, ordered_bits(symmetric ? -x : x));
+        hi = max(hi, ordered_bits(x));
+    }
+    atomic_min(&range[0], lo);
+    atomic_max(&range[1], hi);
+}
+// Normalizes the real part with the limits of range and writes packed RGB8 through the lookup table.
+// Pixel (x, y) is cell (x, y), like image_from_array.
+__kernel void colormap_rgb(__global float2* in, __global uchar* out, __global uchar* lut, int lut_size, __global int* range, int N) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    float lo = from_ordered_bits(range[0]);
+    float hi = from_ordered_bits(range[1]);
+    float t = hi > lo ? clamp((in[i*N +j].x - lo) / (hi - lo), 0.0f, 1.0f) : 0.5f;
+    int c = 3 * (int)round(t * (float)(lut_size - 1));
+    int p = 3 * (j*N + i);
+    out[p] = lut[c];
+    out[p + 1] = lut[c + 1];
+    out[p + 2] = lut[c + 2];
+}
+__kernel void real_part(__global float2* in, __global float* out) {
+    out[get_global_id(0)] = in[get_global_id(0)].x;
+}
+__kernel void to_complex(__global float* in, __global float2* out) {
+    out[get_global_id(0)] = (float2)(in[get_global_id(0)], 0);
+}
+// Free-slip box : w_hat holds the DST-II coefficients of w, mode (i+1, j+1), scalar is pi/L.
+// psi_hat gets the DST-II coefficients of psi. ux = dj psi is a cosine series along j and uy = -di psi
+// along i, their DCT-II coefficients are shifted by one mode, mode N vanishing on the grid.
+__kernel void inv_mlap_free_slip(__global float* w_hat, __global float* psi_hat, __global float* ux_hat, __global float* uy_hat, int N, float scalar) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    float s2 = scalar * scalar;
+    psi_hat[i*N +j] = w_hat[i*N +j] / (s2 * (float)((i+1)*(i+1) + (j+1)*(j+1)));
+    ux_hat[i*N +j] = j == 0 ? 0 : scalar * (float)j * w_hat[i*N +j-1] / (s2 * (float)((i+1)*(i+1) + j*j));
+    uy_hat[i*N +j] = i == 0 ? 0 : -scalar * (float)i * w_hat[(i-1)*N +j] / (s2 * (float)(i*i + (j+1)*(j+1)));
+}
+// advection in a box with walls : cell centered grid, departure points clamped to the domain.
+__kernel void advection_walls(__global float2* w_in, __global float2* w_out, __global float2* ux, __global float2* uy, int N, float L, float dt) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+
+    float ci = clamp((float)i - dt*ux[i*N+j].x*(float)N/L, 0.0f, (float)(N-1));
+    float cj = clamp((float)j - dt*uy[i*N+j].x*(float)N/L, 0.0f, (float)(N-1));
+    int ei = min((int) floor(ci), N-2);
+    int ej = min((int) floor(cj), N-2);
+    float di = ci - (float)ei;
+    float dj = cj - (float)ej;
+
+    float s = 0;
+    s += (1-di)*(1-dj) * w_in[ ei   *N + ej   ].x;
+    s += (1-di)*   dj  * w_in[ ei   *N + ej+1 ].x;
+    s +=    di *(1-dj) * w_in[(ei+1)*N + ej   ].x;
+    s +=    di *   dj  * w_in[(ei+1)*N + ej+1 ].x;
+
+    w_out[i*N +j].x = s;
+    w_out[i*N +j].y = 0;
+}
+// 3/2-rule advection, M = 3N/2 : mode k of the N grid is mode k of the M grid, modes N/2 to M-N/2
+// of the M grid are the zero block skipped by VkFFT.
+int padded_index(int k, int N, int M) {
+    return 2*k >= N ? k - N + M : k;
+}
+// Spectra of ux, uy, di w and dj w written one after the other in out, in the layout of the M grid.
+// Scaled by (M/N)^2 so that the normalized inverse on the M grid gives the same physical values.
+__kernel void pad_gradients(__global float2* w_hat, __global float2* out, int N, int M, float scalar) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    float freqi = scalar * ((float)i - (float)N * (2*i >= N));
+    float freqj = scalar * ((float)j - (float)N * (2*j >= N));
+    float2 w = w_hat[i*N +j] * ((float)M * (float)M / ((float)N * (float)N));
+    float2 p = w / (freqi*freqi + freqj*freqj + ((i==0) && (j==0)));
+    int len = M*M;
+    int k = padded_index(i, N, M)*M + padded_index(j, N, M);
+    out[k] = (float2)(-p.y * freqj, p.x * freqj);
+    out[len + k] = (float2)(p.y * freqi, -p.x * freqi);
+    out[2*len + k] = (float2)(-w.y * freqi, w.x * freqi);
+    out[3*len + k] = (float2)(-w.y * freqj, w.x * freqj);
+}
+// u.grad(w) on the M grid, written over ux.
+__kernel void advection_product(__global float2* fields, int len) {
+    int k = get_global_id(0);
+    float ux = fields[k].x;
+    float uy = fields[len + k].x;
+    fields[k] = (float2)(ux * fields[2*len + k].x + uy * fields[3*len + k].x, 0);
+}
+// Runge-Kutta stage a * w0_hat + b * (w_hat - dt * u.grad(w)), the modes of the N grid read back
+// from the M grid. w_out may be w_hat.
+__kernel void advection_stage_hat(__global float2* w0_hat, __global float2* w_hat, __global float2* nl_hat, __global float2* w_out, int N, int M, float dt, float a, float b) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    float2 nl = nl_hat[padded_index(i, N, M)*M + padded_index(j, N, M)];
+    float2 w = w_hat[i*N +j] - dt * ((float)N * (float)N / ((float)M * (float)M)) * nl;
+    w_out[i*N +j] = a * w0_hat[i*N +j] + b * w;
+}
+// Diffusion of a passive scalar over one step, exact in spectral space, scalar is 2*pi/L
+__kernel void diffusion_hat(__global float2* c_hat, int N, float scalar, float kappa_dt) {
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    float freqi = scalar * ((float)i - (float)N * (2*i >= N));
+    float freqj = scalar * ((float)j - (float)N * (2*j >= N));
+    c_hat[i*N +j] *= exp(-kappa_dt * (freqi*freqi + freqj*freqj));
+}
@@ -4,21 +4,30 @@ extern crate ocl;
 extern crate ocl_vkfft;
 extern crate rand;
 
+pub mod benchmark;
+pub mod checks;
+pub mod colormap;
+pub mod fields;
+pub mod free_slip;
+pub mod memory;
+pub mod nonlinear;
+pub mod overlay;
+pub mod pipeline;
+pub mod profiling;
+pub mod scalars;
+pub mod schedule;
 pub mod utils;
+pub mod video;
 
 use anyhow::{anyhow, Result};
 use indicatif::ProgressBar;
-use num::complex::{Complex32, ComplexFloat};
-use ocl::ocl_core::ClDeviceIdPtr;
-use ocl_vkfft::{
-    VkFFTApplication, VkFFTConfiguration, VkFFTLaunchParams, VkFFTResult_VKFFT_SUCCESS,
-};
+use num::complex::Complex32;
+use ocl::Buffer;
+use ocl_vkfft::{Plan, PlanBuilder};
+use schedule::{Cadence, Schedule};
 use std::f32::consts::PI;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Instant;
-use std::time::SystemTime;
-use ndarray::Array2;
 use utils::new_buffer;
 //use std::thread;
 //use core::time;
@@ -27,6 +36,98 @@ const SRC: &str = include_str!("kernels.cl");
 const N: usize = usize::pow(2, 12);
 const L: f32 = 2.0 * PI;
 
+// Colors of the video frames and of the vorticity snapshots. The video is colormapped on the
+// device, which does not support percentile limits.
+const COLORMAP: colormap::Colormap = colormap::Colormap::RedWhiteBlue;
+const LIMITS: colormap::Limits = colormap::Limits::Symmetric;
+const NORMALIZATION: colormap::Normalization = colormap::Normalization::Running;
+
+// In-process GIF or Y4M encoding avoids the dependency on an ffmpeg binary.
+const VIDEO: video::VideoConfig = video::VideoConfig {
+    format: video::VideoFormat::Ffmpeg(video::FfmpegOptions {
+        codec: "libx264",
+        crf: 19,
+        pixel_format: "yuv420p",
+        threads: 2,
+    }),
+    path: None,
+    fps: 25,
+    resolution: Some(1024),
+};
+
+// Field rendered in the video, and fields saved as PNG snapshots during and at the end of the run.
+// Passive scalars use their own colormap with per-frame limits.
+const VIDEO_FIELD: fields::Field = fields::Field::Vorticity;
+const SNAPSHOT_FIELDS: &[fields::Field] = &[fields::Field::VelocityX, fields::Field::VelocityY];
+
+// Annotations drawn on the video frames and the vorticity snapshots.
+const OVERLAY: Option<overlay::Overlay> = Some(overlay::Overlay {
+    colorbar: true,
+    title: true,
+    scale_bar: Some(L / 4.0),
+    domain: L,
+});
+
+// A free-slip box solves the Poisson equation with sine and cosine transforms instead of the FFT.
+const BOUNDARY: free_slip::Boundary = free_slip::Boundary::Periodic;
+
+// Velocity from the vorticity spectrum in one fused kernel instead of inv_mlap, diff_y and mdiff_x.
+// Viscosity and dealiasing are only applied by the fused kernel.
+const FUSED_VELOCITY: bool = true;
+const VISCOSITY: f32 = 0.0;
+const DEALIAS: bool = false;
+// Pseudo-spectral advection computes u·∇ω on a 3N/2 grid (3/2 rule) with an explicit RK3 step,
+// dt must then satisfy the CFL condition dt (|ux| + |uy|)max π N / L ≤ √3.
+const ADVECTION: nonlinear::Advection = nonlinear::Advection::SemiLagrangian;
+// Passive scalars advected with the vorticity and measured on the DIAGNOSTICS schedule. The k-th
+// is rendered as fields::Field::Scalar(k) in the video or the snapshots, e.g.
+// &[scalars::ScalarConfig {
+//     name: "dye",
+//     diffusivity: 0.0,
+//     initial: scalars::Initial::Stripes(8),
+//     colormap: colormap::Colormap::Viridis,
+// }]
+const SCALARS: &[scalars::ScalarConfig] = &[];
+
+// Times both velocity computations before the run.
+const BENCHMARK_FUSED: bool = false;
+
+// Measures the FFT and the full step for each size instead of running the simulation.
+const BENCHMARK: Option<benchmark::BenchmarkConfig> = None;
+
+// Compiled FFT plans are saved there and reused by later runs, `None` compiles them every run.
+const PLAN_CACHE: Option<&str> = Some("plan_cache");
+
+// Prints the kernels, radices and buffers VkFFT chose for the FFTs of the loop after the run.
+const PLAN_REPORT: bool = false;
+
+// Frames in flight between the device and the encoder thread.
+const STAGING_SLOTS: usize = 3;
+
+// Output schedules, the loop only waits for the device when one of them is due.
+const FRAMES: Schedule = Schedule::EverySteps(1);
+const SNAPSHOTS: Schedule = Schedule::Never;
+const CHECKPOINTS: Schedule = Schedule::Never;
+const DIAGNOSTICS: Schedule = Schedule::EverySteps(10);
+const BLOWUP_GUARD: Schedule = Schedule::EverySteps(50);
+
+// Optional incompressibility and conservation self-checks, run on the DIAGNOSTICS schedule with
+// a full readback of the fields, e.g.
+// Some(checks::Thresholds {
+//     divergence: 1e-4,
+//     circulation: 1e-4,
+//     energy: 1e-2,
+//     abort: false,
+// })
+const CHECKS: Option<checks::Thresholds> = None;
+
+// Device time of each stage of the loop, printed at the end of the run and optionally saved as JSON.
+const PROFILING: bool = false;
+const PROFILE_JSON: Option<&str> = Some("profile.json");
+
+// Stop the run when the vorticity becomes non-finite or its max grows by this factor.
+const BLOWUP_GROWTH: f32 = 100.0;
+
 fn trivial() -> Result<()> {
     let _dx = L / N as f32;
     let dt: f32 = 3f32;
@@ -36,49 +137,61 @@ fn trivial() -> Result<()> {
     let device = ocl::Device::first(&platform)?;
     let context = ocl::Context::builder().build()?;
     let program = ocl::Program::builder().src(SRC).build(&context)?;
-    let queue = ocl::Queue::new(&context, device, None)?;
+    memory::preflight(&device, N, footprint, |m| VIDEO.frame_size(m).is_ok())?;
+    let queue_flags = if PROFILING {
+        Some(ocl::flags::QUEUE_PROFILING_ENABLE)
+    } else {
+        None
+    };
+    let queue = ocl::Queue::new(&context, device, queue_flags)?;
+    // Used by the encoder thread to map the staging buffers of the video frames.
     let transfer_queue = ocl::Queue::new(&context, device, None)?;
 
     let init_data = utils::noise2d(N);
-    let mut w_back_data = Array2::<Complex32>::zeros((N, N));
+    let frame_size = VIDEO.frame_size(N)?;
 
     let w_buffer = new_buffer(&queue, N)?;
     let wnew_buffer = new_buffer(&queue, N)?;
     let what_buffer = new_buffer(&queue, N)?;
     let psihat_buffer = new_buffer(&queue, N)?;
-    let dxu_buffer = new_buffer(&queue, N)?;
-    let dyu_buffer = new_buffer(&queue, N)?;
+    // Both velocity components in one buffer, so that one batched FFT transforms them.
+    let u_buffer = Buffer::<Complex32>::builder()
+        .queue(queue.clone())
+        .len(2 * N * N)
+        .build()?;
+    let dxu_buffer = u_buffer.create_sub_buffer(None, 0, N * N)?;
+    let dyu_buffer = u_buffer.create_sub_buffer(None, N * N, N * N)?;
     wnew_buffer
         .write(init_data.as_slice().ok_or(anyhow!("Oh no!"))?)
         .enq()?;
-    utils::plot_from_gpu(&wnew_buffer, "plot/in.png")?;
-
-    let config = VkFFTConfiguration {
-        FFTdim: 2,
-        size: [N as u64, N as u64, 0, 0],
-        numberBatches: 1,
-        device: &mut device.as_ptr(),
-        context: &mut context.as_ptr(),
-        bufferSize: &mut (8 * (N * N) as u64), // 8 = sizeof(Complex<f32>)
-        normalize: 1,
-        isInputFormatted: 1,
-        ..Default::default()
+    // One color scale per rendered field, kept across the snapshots and shared with the video.
+    let mut scales = vec![];
+    for field in [&[fields::Field::Vorticity, VIDEO_FIELD], SNAPSHOT_FIELDS].concat() {
+        if !scales.iter().any(|(f, _)| *f == field) {
+            scales.push((field, Arc::new(Mutex::new(color_scale(field)?))));
+        }
+    }
+    let annotation = overlay::Annotation {
+        field: "vorticity",
+        time: 0.0,
+        step: 0,
     };
-
-    let mut app = VkFFTApplication {
-        ..Default::default()
+    overlay::render(
+        &init_data.mapv(|x| x.re),
+        &mut *lock_scale(&scales, fields::Field::Vorticity)?,
+        OVERLAY.as_ref(),
+        &annotation,
+    )?
+    .save("plot/in.png")?;
+
+    let cached = |builder: PlanBuilder| match PLAN_CACHE {
+        Some(dir) => builder.cache(dir),
+        None => builder,
     };
-
-    let res = unsafe { ocl_vkfft::initializeVkFFT(&mut app, config) };
-    assert_eq!(res, VkFFTResult_VKFFT_SUCCESS);
+    let mut fft = cached(Plan::builder(&[N, N]).out_of_place(true)).build(&context, device)?;
+    let mut fft_velocity = cached(Plan::builder(&[N, N]).batches(2)).build(&context, device)?;
 
     // ------------------------------------------------------------------------- //
-    let mut launch_what = VkFFTLaunchParams {
-        commandQueue: &mut queue.as_ptr(),
-        inputBuffer: &mut w_buffer.as_ptr(),
-        buffer: &mut what_buffer.as_ptr(),
-        ..Default::default()
-    };
 
     // Diffusion new_w -> what -> what -> w
 
@@ -124,25 +237,85 @@ fn trivial() -> Result<()> {
             .arg(2.0 * PI / L)
             .build()?
     };
-    let mut launch_dxu = VkFFTLaunchParams {
-        commandQueue: &mut queue.as_ptr(),
-        inputBuffer: &mut dxu_buffer.as_ptr(),
-        buffer: &mut dxu_buffer.as_ptr(),
-        ..Default::default()
+    let kernel_velocity = unsafe {
+        ocl::Kernel::builder()
+            .program(&program)
+            .queue(queue.clone())
+            .name("velocity_hat")
+            .global_work_size([N, N])
+            .disable_arg_type_check()
+            .arg(&what_buffer)
+            .arg(&psihat_buffer)
+            .arg(&dxu_buffer)
+            .arg(&dyu_buffer)
+            .arg(N as i32)
+            .arg(2.0 * PI / L)
+            .arg(VISCOSITY * dt)
+            .arg(DEALIAS as i32)
+            .build()?
     };
+    if (VISCOSITY > 0.0 || DEALIAS) && !FUSED_VELOCITY {
+        return Err(anyhow!("Viscosity and dealiasing need FUSED_VELOCITY"));
+    }
 
-    let mut launch_dyu = VkFFTLaunchParams {
-        commandQueue: &mut queue.as_ptr(),
-        inputBuffer: &mut dyu_buffer.as_ptr(),
-        buffer: &mut dyu_buffer.as_ptr(),
-        ..Default::default()
-    };
+    let mut free_slip = None;
+    if BOUNDARY == free_slip::Boundary::FreeSlip {
+        // These all assume a periodic box.
+        let periodic_fields = [fields::Field::Streamfunction, fields::Field::OkuboWeiss];
+        if VISCOSITY > 0.0 || DEALIAS || CHECKS.is_some() {
+            return Err(anyhow!(
+                "Viscosity, dealiasing and self-checks need a periodic box"
+            ));
+        }
+        if [&[VIDEO_FIELD], SNAPSHOT_FIELDS]
+            .concat()
+            .iter()
+            .any(|f| periodic_fields.contains(f))
+        {
+            return Err(anyhow!(
+                "Streamfunction and Okubo-Weiss fields need a periodic box"
+            ));
+        }
+        free_slip = Some(free_slip::FreeSlip::new(
+            &program,
+            &queue,
+            &context,
+            device,
+            N,
+            L,
+            &w_buffer,
+            &dxu_buffer,
+            &dyu_buffer,
+        )?);
+    }
+
+    let mut padded_nonlinear = None;
+    if ADVECTION == nonlinear::Advection::PseudoSpectral {
+        if BOUNDARY != free_slip::Boundary::Periodic {
+            return Err(anyhow!("Pseudo-spectral advection needs a periodic box"));
+        }
+        // Advected spectrum in wnew_buffer, transformed back in place.
+        padded_nonlinear = Some(nonlinear::PaddedNonlinear::new(
+            &program,
+            &queue,
+            &context,
+            device,
+            N,
+            L,
+            dt,
+            &what_buffer,
+            &wnew_buffer,
+        )?);
+    }
 
     let kernel_advection = unsafe {
         ocl::Kernel::builder()
             .program(&program)
             .queue(queue.clone())
-            .name("advection")
+            .name(match BOUNDARY {
+                free_slip::Boundary::Periodic => "advection",
+                free_slip::Boundary::FreeSlip => "advection_walls",
+            })
             .global_work_size([N, N])
             .disable_arg_type_check()
             .arg(&w_buffer)
@@ -155,110 +328,424 @@ fn trivial() -> Result<()> {
             .build()?
     };
 
+    let mut passive_scalars = None;
+    if !SCALARS.is_empty() {
+        passive_scalars = Some(scalars::PassiveScalars::new(
+            &program,
+            &queue,
+            N,
+            L,
+            dt,
+            BOUNDARY,
+            SCALARS,
+            &dxu_buffer,
+            &dyu_buffer,
+        )?);
+    }
+
+    let mut self_checks = match CHECKS {
+        Some(thresholds) => Some(checks::SelfChecks::new(&program, &queue, N, L, thresholds)?),
+        None => None,
+    };
+
+    let requested = [&[VIDEO_FIELD], SNAPSHOT_FIELDS].concat();
+    let sources = fields::Sources {
+        w: w_buffer.clone(),
+        psihat: psihat_buffer.clone(),
+        ux: dxu_buffer.clone(),
+        uy: dyu_buffer.clone(),
+        scalars: passive_scalars
+            .as_ref()
+            .map_or(vec![], scalars::PassiveScalars::fields),
+    };
+    let field_renderer = fields::FieldRenderer::new(&program, &queue, N, L, sources, &requested)?;
+
+    let mut blowup_guard =
+        checks::BlowupGuard::new(&program, &queue, &wnew_buffer, N, BLOWUP_GROWTH)?;
+
     // ------------------------------------------------------------------------- //
 
-    let sys_time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)?
-        .as_secs();
-
-    let mut ffmpeg = Command::new("ffmpeg")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .args([
-            "-hide_banner",
-            "-loglevel",
-            "warning",
-            "-f",
-            "rawvideo",
-            "-pixel_format",
-            "rgb24",
-            "-video_size",
-            &format!("{}x{}", N, N),
-            "-i",
-            "pipe:",
-            "-threads",
-            "2",
-            "-crf",
-            "19",
-            &format!("videos/{}.mp4", sys_time),
-        ])
-        .spawn()?;
-
-    let mut ffmpeg_in = ffmpeg.stdin.take().unwrap();
+    let video = video::VideoWriter::new(&VIDEO, frame_size)?;
+    let downsampler = if frame_size < N {
+        Some(fields::Downsampler::new(&program, &queue, N, frame_size)?)
+    } else {
+        None
+    };
+    let mut pipeline = pipeline::FramePipeline::new(
+        &program,
+        &queue,
+        transfer_queue,
+        frame_size,
+        STAGING_SLOTS,
+        field_renderer.name(VIDEO_FIELD),
+        shared_scale(&scales, VIDEO_FIELD)?,
+        OVERLAY,
+        video,
+    )?;
 
     queue.finish()?;
     println!("Initialization complete. (fake)");
+    if BENCHMARK_FUSED {
+        benchmark_velocity(
+            &queue,
+            &kernel_velocity,
+            &[&kernel_invmlap, &kernel_dyu, &kernel_dxu],
+            100,
+        )?;
+    }
     let pb = ProgressBar::new(niter);
 
     // ------------------------------------------------------------------------- //
+    let mut frames = Cadence::new(FRAMES)?;
+    let mut snapshots = Cadence::new(SNAPSHOTS)?;
+    let mut checkpoints = Cadence::new(CHECKPOINTS)?;
+    let mut diagnostics = Cadence::new(DIAGNOSTICS)?;
+    let mut guard = Cadence::new(BLOWUP_GUARD)?;
+    let mut profiler = profiling::Profiler::new(PROFILING);
+
     let instant = Instant::now();
     unsafe {
-        for _ in 0..niter {
-            wnew_buffer
-                .read(w_back_data.as_slice_mut().ok_or(anyhow!("Noo"))?)
-                .queue(&transfer_queue)
-                .enq()?;
+        for step in 0..niter {
+            let time = step as f32 * dt;
+            let span = profiler.start(&queue)?;
             wnew_buffer.copy(&w_buffer, None, None).enq()?;
-
-            let res = ocl_vkfft::VkFFTAppend(&mut app, -1, &mut launch_what);
-            assert_eq!(res, VkFFTResult_VKFFT_SUCCESS);
-
-            kernel_invmlap.enq()?;
-
-            kernel_dyu.enq()?;
-            let res = ocl_vkfft::VkFFTAppend(&mut app, 1, &mut launch_dyu);
-            assert_eq!(res, VkFFTResult_VKFFT_SUCCESS);
-
-            kernel_dxu.enq()?;
-            let res = ocl_vkfft::VkFFTAppend(&mut app, 1, &mut launch_dxu);
-            assert_eq!(res, VkFFTResult_VKFFT_SUCCESS);
-
-            transfer_queue.finish()?;
-            kernel_advection.enq()?;
-
-            let im = utils::image_from_array(&w_back_data.mapv(|x| x.re()))?;
-            ffmpeg_in.write_all(&im.to_vec())?;
-            queue.finish()?;
+            profiler.end("copy", &queue, span)?;
+
+            if let Some(free_slip) = free_slip.as_mut() {
+                let span = profiler.start(&queue)?;
+                free_slip.velocity(&queue)?;
+                profiler.end("free_slip_velocity", &queue, span)?;
+            } else {
+                let span = profiler.start(&queue)?;
+                fft.forward(&queue, &w_buffer, &what_buffer)?;
+                profiler.end("fft_forward", &queue, span)?;
+
+                if FUSED_VELOCITY {
+                    profiler.kernel("velocity_hat", &kernel_velocity)?;
+                } else {
+                    profiler.kernel("inv_mlap", &kernel_invmlap)?;
+                    profiler.kernel("mdiff_x", &kernel_dyu)?;
+                    profiler.kernel("diff_y", &kernel_dxu)?;
+                }
+
+                let span = profiler.start(&queue)?;
+                fft_velocity.inverse(&queue, &u_buffer)?;
+                profiler.end("fft_inverse", &queue, span)?;
+            }
+
+            if VISCOSITY > 0.0 {
+                let span = profiler.start(&queue)?;
+                what_buffer.copy(&w_buffer, None, None).enq()?;
+                // Diffused vorticity back to physical space.
+                fft.inverse(&queue, &w_buffer)?;
+                profiler.end("diffusion", &queue, span)?;
+            }
+
+            if diagnostics.due(step, time, dt) {
+                if let Some(self_checks) = self_checks.as_mut() {
+                    let span = profiler.start(&queue)?;
+                    let diag =
+                        self_checks.measure(&mut fft, &w_buffer, &dxu_buffer, &dyu_buffer)?;
+                    profiler.end("diagnostics", &queue, span)?;
+                    self_checks.check(step, diag)?;
+                }
+                if let Some(passive_scalars) = passive_scalars.as_mut() {
+                    let span = profiler.start(&queue)?;
+                    passive_scalars.measure(&queue, &mut fft, step, time)?;
+                    profiler.end("scalar_diagnostics", &queue, span)?;
+                }
+            }
+
+            if frames.due(step, time, dt) {
+                let span = profiler.start(&queue)?;
+                let mut frame = field_renderer.compute(&mut fft, VIDEO_FIELD)?;
+                if let Some(downsampler) = downsampler.as_ref() {
+                    frame = downsampler.apply(frame)?;
+                }
+                pipeline.submit(&queue, frame, step, time)?;
+                profiler.end("frame", &queue, span)?;
+            }
+
+            if snapshots.due(step, time, dt) {
+                let span = profiler.start(&queue)?;
+                save_snapshots(
+                    &field_renderer,
+                    &scales,
+                    &mut fft,
+                    step,
+                    dt,
+                    &format!("_{:06}", step),
+                )?;
+                profiler.end("snapshots", &queue, span)?;
+            }
+
+            if checkpoints.due(step, time, dt) {
+                std::fs::create_dir_all("checkpoints")?;
+                let span = profiler.start(&queue)?;
+                utils::save_raw(&w_buffer, &format!("checkpoints/{:06}.bin", step))?;
+                profiler.end("checkpoint", &queue, span)?;
+            }
+
+            if let Some(padded_nonlinear) = padded_nonlinear.as_mut() {
+                let span = profiler.start(&queue)?;
+                padded_nonlinear.step(&queue)?;
+                fft.inverse(&queue, &wnew_buffer)?;
+                profiler.end("advection_padded", &queue, span)?;
+            } else {
+                profiler.kernel("advection", &kernel_advection)?;
+            }
+
+            if let Some(passive_scalars) = passive_scalars.as_ref() {
+                let span = profiler.start(&queue)?;
+                passive_scalars.step(&queue, &mut fft)?;
+                profiler.end("scalars", &queue, span)?;
+            }
+
+            // wnew_buffer holds the field of the next step.
+            if guard.due(step + 1, time + dt, dt) {
+                let span = profiler.start(&queue)?;
+                let blowup = blowup_guard.check(step + 1)?;
+                profiler.end("blowup_guard", &queue, span)?;
+                if let Some((bad_step, blowup)) = blowup {
+                    return Err(dump_blowup(&blowup_guard, bad_step, blowup)?);
+                }
+            }
             pb.inc(1);
         }
     }
     queue.finish()?;
+    if let Some((bad_step, blowup)) = blowup_guard.resolve()? {
+        return Err(dump_blowup(&blowup_guard, bad_step, blowup)?);
+    }
     println!("Loop time: {:?}", instant.elapsed());
+    if PLAN_REPORT {
+        println!("Vorticity FFT :\n{}", fft.report());
+        println!("Velocity FFT :\n{}", fft_velocity.report());
+    }
+    if profiler.enabled() {
+        let report = profiler.report()?;
+        profiling::print_report(&report);
+        if let Some(path) = PROFILE_JSON {
+            profiling::write_json(&report, path)?;
+            println!("Profile written to {}", path);
+        }
+    }
 
     // ------------------------------------------------------------------------- //
 
-    ffmpeg_in.flush()?;
-    std::mem::drop(ffmpeg_in);
-    let ffmpeg_output = ffmpeg.wait_with_output().unwrap();
+    let video_path = pipeline.finish()?;
+    println!("Video written to {}", video_path);
 
-    match ffmpeg_output.status.code() {
-        Some(0) => println!(
-            "OK FFMPEG: {}",
-            String::from_utf8_lossy(&ffmpeg_output.stdout)
-        ),
-        Some(code) => println!("Error {}", code),
-        None => {}
+    let annotation = overlay::Annotation {
+        field: "vorticity",
+        time: niter as f32 * dt,
+        step: niter,
+    };
+    overlay::render(
+        &utils::get_from_gpu(&wnew_buffer)?.mapv(|x| x.re),
+        &mut *lock_scale(&scales, fields::Field::Vorticity)?,
+        OVERLAY.as_ref(),
+        &annotation,
+    )?
+    .save("plot/out.png")?;
+
+    // The sources of the derived fields are those of the last step, computed from w_buffer.
+    save_snapshots(&field_renderer, &scales, &mut fft, niter - 1, dt, "")?;
+    if let Some(passive_scalars) = passive_scalars.as_ref() {
+        passive_scalars.write_history("plot")?;
     }
 
-    utils::plot_from_gpu(&wnew_buffer, "plot/out.png")?;
-    utils::plot_from_gpu(&dxu_buffer, "plot/dxu.png")?;
-    utils::plot_from_gpu(&dyu_buffer, "plot/dyu.png")?;
-
     utils::printmax(&w_buffer, "w")?;
     utils::printmax(&wnew_buffer, "wnew")?;
     utils::printmax(&dxu_buffer, "dxu")?;
     utils::printmax(&dyu_buffer, "dyu")?;
 
-    unsafe {
-        ocl_vkfft::deleteVkFFT(&mut app);
+    println!("End trivial.");
+    Ok(())
+}
+
+/// Device memory allocated for a grid of side `n` with the current configuration.
+fn footprint(n: usize) -> memory::Footprint {
+    let field = 8 * n * n; // 8 = sizeof(Complex<f32>)
+    let mut f = memory::Footprint::default();
+    for name in ["w", "wnew", "what", "psihat"] {
+        f.add(name, field);
+    }
+    f.add("velocity", 2 * field);
+    // Upper bound, VkFFT only allocates a temporary buffer when a transform needs several uploads.
+    f.add("fft temp", field);
+    f.add("fft velocity temp", 2 * field);
+    if CHECKS.is_some() {
+        f.add("ux_hat", field);
+        f.add("uy_hat", field);
+    }
+    if BOUNDARY == free_slip::Boundary::FreeSlip {
+        for name in ["w_hat real", "psi_hat real", "ux_hat real", "uy_hat real"] {
+            f.add(name, 4 * n * n);
+        }
+    }
+    if ADVECTION == nonlinear::Advection::PseudoSpectral {
+        let m = nonlinear::PaddedNonlinear::padded_size(n);
+        f.add("padded gradients", 4 * 8 * m * m);
+        f.add("padded fft temp", 4 * 8 * m * m);
+        f.add("advection stage", field);
+    }
+    for scalar in SCALARS {
+        f.add(scalar.name, 2 * field);
+    }
+    if !SCALARS.is_empty() {
+        f.add("scalar_hat", field);
+    }
+    f.add("last_good", field);
+    f.add("checked", field);
+    f.add("render", field);
+    if [&[VIDEO_FIELD], SNAPSHOT_FIELDS]
+        .concat()
+        .contains(&fields::Field::OkuboWeiss)
+    {
+        f.add("sn", field);
+        f.add("ss", field);
+    }
+    let m = VIDEO.resolution.map_or(n, |r| r.min(n));
+    if m < n {
+        f.add("downsampled", 8 * m * m);
     }
+    f.add("rgb", 3 * m * m);
+    for _ in 0..STAGING_SLOTS {
+        f.add("staging", 3 * m * m);
+    }
+    f
+}
 
-    println!("End trivial.");
+/// Times the fused velocity kernel against the sequence of kernels it replaces.
+fn benchmark_velocity(
+    queue: &ocl::Queue,
+    fused: &ocl::Kernel,
+    sequence: &[&ocl::Kernel],
+    repeats: u32,
+) -> Result<()> {
+    let time = |kernels: &[&ocl::Kernel]| -> Result<f64> {
+        queue.finish()?;
+        let instant = Instant::now();
+        for _ in 0..repeats {
+            for kernel in kernels {
+                unsafe {
+                    kernel.enq()?;
+                }
+            }
+        }
+        queue.finish()?;
+        Ok(instant.elapsed().as_secs_f64() * 1e3 / repeats as f64)
+    };
+    let t_sequence = time(sequence)?;
+    let t_fused = time(&[fused])?;
+    println!(
+        "Velocity : {} kernels {:.3} ms, fused {:.3} ms ({:.2}x)",
+        sequence.len(),
+        t_sequence,
+        t_fused,
+        t_sequence / t_fused
+    );
+    Ok(())
+}
+
+/// Saves the derived fields of `step` as annotated PNGs named `plot/<field><suffix>.png`.
+/// Dumps the last good and the first bad fields of a blow-up, returns the error to stop the run.
+fn dump_blowup(
+    guard: &checks::BlowupGuard,
+    bad_step: u64,
+    blowup: checks::Blowup,
+) -> Result<anyhow::Error> {
+    let (good_step, last_good) = guard.last_good();
+    std::fs::create_dir_all("dump")?;
+    utils::save_raw(last_good, &format!("dump/last_good_{}.bin", good_step))?;
+    utils::save_raw(
+        guard.first_bad(),
+        &format!("dump/first_bad_{}.bin", bad_step),
+    )?;
+    utils::plot_from_gpu(
+        last_good,
+        &format!("dump/last_good_{}.png", good_step),
+        &mut colormap::ColorScale::per_frame()?,
+    )?;
+    Ok(anyhow!("Blow-up at step {} : {}", bad_step, blowup))
+}
+
+/// Colors of `field` : the configured ones, or those of the scalar for a passive scalar.
+fn color_scale(field: fields::Field) -> Result<colormap::ColorScale> {
+    match field {
+        fields::Field::Scalar(k) => colormap::ColorScale::new(
+            SCALARS
+                .get(k)
+                .ok_or(anyhow!("No passive scalar of index {}", k))?
+                .colormap,
+            colormap::Limits::Asymmetric,
+            colormap::Normalization::PerFrame,
+        ),
+        _ => colormap::ColorScale::new(COLORMAP, LIMITS, NORMALIZATION),
+    }
+}
+
+/// Persistent color scales of the rendered fields, see `color_scale`.
+type Scales = [(fields::Field, Arc<Mutex<colormap::ColorScale>>)];
+
+fn shared_scale(scales: &Scales, field: fields::Field) -> Result<Arc<Mutex<colormap::ColorScale>>> {
+    scales
+        .iter()
+        .find(|(f, _)| *f == field)
+        .map(|(_, scale)| scale.clone())
+        .ok_or(anyhow!("No color scale for {:?}", field))
+}
+
+fn lock_scale(
+    scales: &Scales,
+    field: fields::Field,
+) -> Result<MutexGuard<'_, colormap::ColorScale>> {
+    scales
+        .iter()
+        .find(|(f, _)| *f == field)
+        .ok_or(anyhow!("No color scale for {:?}", field))?
+        .1
+        .lock()
+        .map_err(|_| anyhow!("Color scale of {:?} poisoned", field))
+}
+
+fn save_snapshots(
+    field_renderer: &fields::FieldRenderer,
+    scales: &Scales,
+    fft: &mut Plan,
+    step: u64,
+    dt: f32,
+    suffix: &str,
+) -> Result<()> {
+    for field in SNAPSHOT_FIELDS {
+        let annotation = overlay::Annotation {
+            field: field_renderer.name(*field),
+            time: step as f32 * dt,
+            step,
+        };
+        let data = utils::get_from_gpu(field_renderer.compute(fft, *field)?)?;
+        overlay::render(
+            &data.mapv(|x| x.re),
+            &mut *lock_scale(scales, *field)?,
+            OVERLAY.as_ref(),
+            &annotation,
+        )?
+        .save(format!(
+            "plot/{}{}.png",
+            field_renderer.name(*field),
+            suffix
+        ))?;
+    }
     Ok(())
 }
 
 fn main() {
-    match trivial() {
+    let result = match BENCHMARK {
+        Some(config) => benchmark::run(&config, SRC),
+        None => trivial(),
+    };
+    match result {
         Ok(()) => println!("Program exited successfully."),
         Err(e) => println!("Not working : {e:?}"),
     }
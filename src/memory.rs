@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Result};
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// Device buffers a configuration allocates, in bytes.
+#[derive(Default)]
+pub struct Footprint {
+    pub buffers: Vec<(&'static str, u64)>,
+}
+
+impl Footprint {
+    pub fn add(&mut self, name: &'static str, bytes: usize) {
+        self.buffers.push((name, bytes as u64));
+    }
+
+    pub fn total(&self) -> u64 {
+        self.buffers.iter().map(|(_, b)| b).sum()
+    }
+
+    pub fn largest(&self) -> (&'static str, u64) {
+        self.buffers
+            .iter()
+            .cloned()
+            .max_by_key(|(_, b)| *b)
+            .unwrap_or(("none", 0))
+    }
+}
+
+/// Fails before any allocation if the footprint of grid size `n` does not fit on `device`,
+/// suggesting the largest power of two grid that fits and that `compatible` accepts, e.g. that
+/// the video resolution divides.
+pub fn preflight(
+    device: &ocl::Device,
+    n: usize,
+    footprint: impl Fn(usize) -> Footprint,
+    compatible: impl Fn(usize) -> bool,
+) -> Result<()> {
+    let global = match device.info(DeviceInfo::GlobalMemSize)? {
+        DeviceInfoResult::GlobalMemSize(bytes) => bytes,
+        _ => return Err(anyhow!("Unexpected answer to CL_DEVICE_GLOBAL_MEM_SIZE")),
+    };
+    let max_alloc = match device.info(DeviceInfo::MaxMemAllocSize)? {
+        DeviceInfoResult::MaxMemAllocSize(bytes) => bytes,
+        _ => return Err(anyhow!("Unexpected answer to CL_DEVICE_MAX_MEM_ALLOC_SIZE")),
+    };
+    let fits = |f: &Footprint| f.total() <= global && f.largest().1 <= max_alloc;
+
+    let required = footprint(n);
+    if fits(&required) {
+        return Ok(());
+    }
+    let (name, largest) = required.largest();
+    let smaller = || {
+        std::iter::successors(Some(n / 2), |m| Some(m / 2))
+            .take_while(|m| *m >= 2)
+            .filter(|m| fits(&footprint(*m)))
+    };
+    let mib = |m: usize| footprint(m).total() as f64 / MIB;
+    let suggestion = match (smaller().next(), smaller().find(|m| compatible(*m))) {
+        (Some(m), Some(c)) if m == c => format!("N = {} would need {:.0} MiB", m, mib(m)),
+        (Some(m), Some(c)) => format!(
+            "N = {} would need {:.0} MiB, N = {} would need {:.0} MiB with a video resolution \
+             that divides it",
+            c,
+            mib(c),
+            m,
+            mib(m)
+        ),
+        (Some(m), None) => format!(
+            "N = {} would need {:.0} MiB with a video resolution that divides it",
+            m,
+            mib(m)
+        ),
+        (None, _) => "no smaller grid fits either".to_string(),
+    };
+    Err(anyhow!(
+        "N = {} needs {:.0} MiB of device memory with a largest buffer of {:.0} MiB ({}), \
+         the device has {:.0} MiB and allocations of at most {:.0} MiB : {}",
+        n,
+        required.total() as f64 / MIB,
+        largest as f64 / MIB,
+        name,
+        global as f64 / MIB,
+        max_alloc as f64 / MIB,
+        suggestion
+    ))
+}
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Result};
+use num::complex::Complex32;
+use ocl::Buffer;
+use ocl_vkfft::Plan;
+use std::f32::consts::PI;
+
+/// Time stepping of the advection of the vorticity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Advection {
+    /// Bilinear interpolation at the departure points, stable for any time step.
+    SemiLagrangian,
+    /// Third order SSP Runge–Kutta step of -u·∇ω computed spectrally and dealiased with the 3/2
+    /// rule. The eigenvalues of -u·∇ are imaginary, so that Euler and second order steps blow up
+    /// without viscosity, whereas this one is stable for dt (|ux| + |uy|)max π N / L ≤ √3.
+    PseudoSpectral,
+}
+
+/// Pseudo-spectral advection term u·∇ω, products on a 3N/2 grid.
+///
+/// The spectra of ux, uy, ∂_iω and ∂_jω are written in the layout of the padded grid and the
+/// padded plans skip the added modes, which are never allocated as a separate copy nor zeroed.
+pub struct PaddedNonlinear {
+    fields: Buffer<Complex32>,
+    product: Buffer<Complex32>,
+    // Spectrum of the first two stages.
+    stage: Buffer<Complex32>,
+    w_hat: Buffer<Complex32>,
+    w_out: Buffer<Complex32>,
+    inverse: Plan,
+    forward: Plan,
+    kernel_pad: ocl::Kernel,
+    kernel_product: ocl::Kernel,
+    kernel_step: ocl::Kernel,
+}
+
+impl PaddedNonlinear {
+    /// Size of the padded grid of an `n` x `n` grid.
+    pub fn padded_size(n: usize) -> usize {
+        3 * n / 2
+    }
+
+    /// Reads the spectrum of the vorticity in `w_hat` and writes the advected vorticity in
+    /// `w_out`, in spectral space.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        program: &ocl::Program,
+        queue: &ocl::Queue,
+        context: &ocl::Context,
+        device: ocl::Device,
+        n: usize,
+        l: f32,
+        dt: f32,
+        w_hat: &Buffer<Complex32>,
+        w_out: &Buffer<Complex32>,
+    ) -> Result<PaddedNonlinear> {
+        if !n.is_multiple_of(2) {
+            return Err(anyhow!("The 3/2 rule needs an even grid, not N = {}", n));
+        }
+        let m = Self::padded_size(n);
+        // ux, uy, ∂_iω and ∂_jω, the product is written over ux.
+        let fields = Buffer::<Complex32>::builder()
+            .queue(queue.clone())
+            .len(4 * m * m)
+            .build()?;
+        let product = fields.create_sub_buffer(None, 0, m * m)?;
+        let stage = Buffer::<Complex32>::builder()
+            .queue(queue.clone())
+            .len(n * n)
+            .build()?;
+
+        // Modes -n/2..n/2 are at both ends of each axis, the middle ones are zero.
+        let padded = |builder: ocl_vkfft::PlanBuilder| {
+            builder
+                .zero_padding(0, n / 2..m - n / 2)
+                .zero_padding(1, n / 2..m - n / 2)
+                .frequency_zero_padding(true)
+        };
+        let inverse = padded(Plan::builder(&[m, m]).batches(4)).build(context, device)?;
+        let forward = padded(Plan::builder(&[m, m])).build(context, device)?;
+
+        let kernel_pad = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("pad_gradients")
+                .global_work_size([n, n])
+                .disable_arg_type_check()
+                .arg(w_hat)
+                .arg(&fields)
+                .arg(n as i32)
+                .arg(m as i32)
+                .arg(2.0 * PI / l)
+                .build()?
+        };
+        let kernel_product = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("advection_product")
+                .global_work_size(m * m)
+                .disable_arg_type_check()
+                .arg(&fields)
+                .arg((m * m) as i32)
+                .build()?
+        };
+        let kernel_step = unsafe {
+            ocl::Kernel::builder()
+                .program(program)
+                .queue(queue.clone())
+                .name("advection_stage_hat")
+                .global_work_size([n, n])
+                .disable_arg_type_check()
+                .arg(w_hat)
+                .arg(w_hat)
+                .arg(&product)
+                .arg(w_out)
+                .arg(n as i32)
+                .arg(m as i32)
+                .arg(dt)
+                .arg(0f32)
+                .arg(1f32)
+                .build()?
+        };
+
+        Ok(PaddedNonlinear {
+            fields,
+            product,
+            stage,
+            w_hat: w_hat.clone(),
+            w_out: w_out.clone(),
+            inverse,
+            forward,
+            kernel_pad,
+            kernel_product,
+            kernel_step,
+        })
+    }
+
+    /// Enqueues the advection step from the current spectrum of the vorticity, in three stages
+    /// w1 = w0 + dt L(w0), w2 = ¾ w0 + ¼ (w1 + dt L(w1)) and w3 = ⅓ w0 + ⅔ (w2 + dt L(w2)).
+    pub fn step(&mut self, queue: &ocl::Queue) -> Result<()> {
+        let stages = [
+            (&self.w_hat, &self.stage, 0.0, 1.0),
+            (&self.stage, &self.stage, 0.75, 0.25),
+            (&self.stage, &self.w_out, 1.0 / 3.0, 2.0 / 3.0),
+        ];
+        for (input, output, a, b) in stages {
+            self.kernel_pad.set_arg(0, input)?;
+            unsafe {
+                self.kernel_pad.enq()?;
+            }
+            self.inverse.inverse(queue, &self.fields)?;
+            unsafe {
+                self.kernel_product.enq()?;
+            }
+            self.forward.forward(queue, &self.product, &self.product)?;
+            self.kernel_step.set_arg(1, input)?;
+            self.kernel_step.set_arg(3, output)?;
+            self.kernel_step.set_arg(7, a as f32)?;
+            self.kernel_step.set_arg(8, b as f32)?;
+            unsafe {
+                self.kernel_step.enq()?;
+            }
+        }
+        Ok(())
+    }
+}
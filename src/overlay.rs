@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Result};
+use image::{ImageBuffer, Rgb};
+use ndarray::Array2;
+use plotters::prelude::*;
+
+use crate::colormap::ColorScale;
+use crate::utils::image_from_array;
+
+/// Which annotations are drawn on top of the rendered frames.
+#[derive(Clone, Copy, Debug)]
+pub struct Overlay {
+    pub colorbar: bool,
+    /// Field name, simulation time and step number.
+    pub title: bool,
+    /// Length of the scale bar in domain units, none if `None`.
+    pub scale_bar: Option<f32>,
+    /// Side of the periodic box, to convert the scale bar length to pixels.
+    pub domain: f32,
+}
+
+/// What the frame shows.
+pub struct Annotation<'a> {
+    pub field: &'a str,
+    pub time: f32,
+    pub step: u64,
+}
+
+/// Colormaps `data` with `scale` and annotates the image if an overlay is given.
+pub fn render(
+    data: &Array2<f32>,
+    scale: &mut ColorScale,
+    overlay: Option<&Overlay>,
+    annotation: &Annotation,
+) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>> {
+    let mut img = image_from_array(data, scale)?;
+    if let Some(overlay) = overlay {
+        annotate(&mut img, overlay, scale, annotation)?;
+    }
+    Ok(img)
+}
+
+/// Draws the overlay onto `img`, the colorbar uses the last limits of `scale`.
+pub fn annotate(
+    img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+    overlay: &Overlay,
+    scale: &ColorScale,
+    annotation: &Annotation,
+) -> Result<()> {
+    let (w, h) = img.dimensions();
+    let font_size = (h / 40).max(12) as i32;
+    let margin = font_size;
+
+    // The colorbar itself is written pixel by pixel, plotters only draws the frame and the text.
+    let bar = (
+        w as i32 - 5 * margin,
+        h as i32 / 6,
+        w as i32 - 4 * margin,
+        5 * h as i32 / 6,
+    );
+    // Frames narrower than the colorbar and its labels are left without one.
+    let colorbar = overlay.colorbar && bar.0 >= 0 && bar.3 - bar.1 > 1;
+    if colorbar {
+        for y in bar.1..bar.3 {
+            let t = 1.0 - (y - bar.1) as f32 / (bar.3 - bar.1 - 1) as f32;
+            let color = scale.color(t);
+            for x in bar.0..bar.2 {
+                img.put_pixel(x as u32, y as u32, color);
+            }
+        }
+    }
+
+    let root = BitMapBackend::with_buffer(img.as_mut(), (w, h)).into_drawing_area();
+    let style = ("sans-serif", font_size).into_font().color(&BLACK);
+    let background = WHITE.mix(0.7).filled();
+
+    if colorbar {
+        let (lo, hi) = scale
+            .range()
+            .ok_or(anyhow!("Colorbar without color limits"))?;
+        root.draw(&Rectangle::new(
+            [(bar.0, bar.1), (bar.2, bar.3)],
+            BLACK.stroke_width(1),
+        ))?;
+        let ticks = 5;
+        for k in 0..ticks {
+            let y = bar.3 - 1 - k * (bar.3 - bar.1 - 1) / (ticks - 1);
+            let value = lo + (hi - lo) * k as f32 / (ticks - 1) as f32;
+            let label = format!("{:.3}", value);
+            root.draw(&Rectangle::new(
+                [
+                    (bar.2 + 2, y - font_size / 2),
+                    (w as i32 - 2, y + font_size / 2),
+                ],
+                background,
+            ))?;
+            root.draw(&PathElement::new(vec![(bar.2, y), (bar.2 + 4, y)], BLACK))?;
+            root.draw(&Text::new(label, (bar.2 + 6, y - font_size / 2), &style))?;
+        }
+    }
+
+    if overlay.title {
+        let lines = [
+            annotation.field.to_string(),
+            format!("t = {:.3}   step {}", annotation.time, annotation.step),
+        ];
+        root.draw(&Rectangle::new(
+            [
+                (margin / 2, margin / 2),
+                (16 * margin, margin + 2 * font_size + margin / 2),
+            ],
+            background,
+        ))?;
+        for (k, line) in lines.into_iter().enumerate() {
+            root.draw(&Text::new(
+                line,
+                (margin, margin + k as i32 * font_size),
+                &style,
+            ))?;
+        }
+    }
+
+    if let Some(length) = overlay.scale_bar {
+        let pixels = (length / overlay.domain * w as f32).round() as i32;
+        let y = h as i32 - 2 * margin;
+        root.draw(&Rectangle::new(
+            [
+                (margin / 2, y - font_size - margin / 2),
+                (pixels + 3 * margin / 2, y + margin / 2),
+            ],
+            background,
+        ))?;
+        root.draw(&Rectangle::new(
+            [(margin, y - 2), (margin + pixels, y + 2)],
+            BLACK.filled(),
+        ))?;
+        root.draw(&Text::new(
+            format!("{:.3}", length),
+            (margin, y - font_size - 2),
+            &style,
+        ))?;
+    }
+
+    root.present()?;
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use anyhow::{anyhow, Result};
+use image::ImageBuffer;
+use num::complex::Complex32;
+use ocl::{Buffer, Event, Queue};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::colormap::{decode_range, ColorScale, DeviceColorScale};
+use crate::overlay::{self, Annotation, Overlay};
+use crate::video::VideoWriter;
+
+/// A frame waiting in a staging slot, `ready` completes when the copy into the slot is done.
+struct Job {
+    slot: usize,
+    ready: Event,
+    step: u64,
+    time: f32,
+}
+
+/// Host-accessible copy of a colormapped frame and of its color limits.
+#[derive(Clone)]
+struct Slot {
+    rgb: Buffer<u8>,
+    range: Buffer<i32>,
+}
+
+/// Asynchronous readback of the video frames.
+///
+/// Frames are colormapped on the device and copied on the compute queue into a ring of
+/// host-accessible staging buffers, then an encoder thread maps them on its own queue, draws the
+/// overlay and writes them to the video. The compute queue only waits when every slot is still
+/// owned by the encoder.
+pub struct FramePipeline {
+    colormap: DeviceColorScale,
+    staging: Vec<Slot>,
+    // Slots given back by the encoder, with the event of their unmap.
+    free: Receiver<(usize, Option<Event>)>,
+    jobs: Option<Sender<Job>>,
+    encoder: Option<JoinHandle<Result<String>>>,
+}
+
+impl FramePipeline {
+    /// `transfer_queue` is used by the encoder thread only, `size` is the side of the frames.
+    /// `scale` may be shared with the snapshots of the same field, the encoder sets its limits to
+    /// those of each frame.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        program: &ocl::Program,
+        queue: &Queue,
+        transfer_queue: Queue,
+        size: usize,
+        slots: usize,
+        field: &'static str,
+        scale: Arc<Mutex<ColorScale>>,
+        overlay: Option<Overlay>,
+        mut video: VideoWriter,
+    ) -> Result<FramePipeline> {
+        let colormap = DeviceColorScale::new(
+            program,
+            queue,
+            size,
+            &*scale.lock().map_err(|_| anyhow!("Color scale poisoned"))?,
+        )?;
+        let mut staging = vec![];
+        for _ in 0..slots {
+            staging.push(Slot {
+                rgb: Buffer::<u8>::builder()
+                    .queue(queue.clone())
+                    .flags(ocl::flags::MEM_ALLOC_HOST_PTR)
+                    .len(3 * size * size)
+                    .build()?,
+                range: Buffer::<i32>::builder()
+                    .queue(queue.clone())
+                    .flags(ocl::flags::MEM_ALLOC_HOST_PTR)
+                    .len(2)
+                    .build()?,
+            });
+        }
+
+        let (free_sender, free) = channel();
+        for slot in 0..slots {
+            free_sender.send((slot, None))?;
+        }
+        let (jobs, job_receiver) = channel::<Job>();
+        let buffers = staging.clone();
+
+        let encoder = std::thread::spawn(move || {
+            for job in job_receiver {
+                job.ready.wait_for()?;
+                let (pixels, range) = {
+                    let slot = &buffers[job.slot];
+                    let mut rgb = unsafe { slot.rgb.map().queue(&transfer_queue).read().enq()? };
+                    let mut range =
+                        unsafe { slot.range.map().queue(&transfer_queue).read().enq()? };
+                    let pixels = rgb.to_vec();
+                    let bits = [range[0], range[1]];
+                    range.unmap().queue(&transfer_queue).enq()?;
+                    let mut unmapped = Event::empty();
+                    rgb.unmap()
+                        .queue(&transfer_queue)
+                        .enew(&mut unmapped)
+                        .enq()?;
+                    transfer_queue.flush()?;
+                    // The main thread may be gone after an error, the slot is not needed then.
+                    let _ = free_sender.send((job.slot, Some(unmapped)));
+                    (pixels, decode_range(bits))
+                };
+                let mut im = ImageBuffer::from_raw(size as u32, size as u32, pixels)
+                    .ok_or(anyhow!("Frame of the wrong size"))?;
+                {
+                    let mut scale = scale.lock().map_err(|_| anyhow!("Color scale poisoned"))?;
+                    if let Some(range) = range {
+                        scale.set_range(range);
+                    }
+                    if let Some(overlay) = overlay.as_ref() {
+                        let annotation = Annotation {
+                            field,
+                            time: job.time,
+                            step: job.step,
+                        };
+                        overlay::annotate(&mut im, overlay, &scale, &annotation)?;
+                    }
+                }
+                video.write_frame(&im)?;
+            }
+            video.finish()
+        });
+
+        Ok(FramePipeline {
+            colormap,
+            staging,
+            free,
+            jobs: Some(jobs),
+            encoder: Some(encoder),
+        })
+    }
+
+    /// Enqueues the colormapping of `frame` and its copy on `queue`, then hands it to the encoder.
+    /// Blocks only if all the staging slots are in use.
+    pub fn submit(
+        &mut self,
+        queue: &Queue,
+        frame: &Buffer<Complex32>,
+        step: u64,
+        time: f32,
+    ) -> Result<()> {
+        let (slot, unmapped) = match self.free.recv() {
+            Ok(free) => free,
+            Err(_) => return Err(self.encoder_error()),
+        };
+        let (rgb, range) = self.colormap.apply(frame)?;
+        let staging = &self.staging[slot];
+        // The queue is in order, the frame is ready once the last copy completes.
+        let mut copy = rgb.copy(&staging.rgb, None, None).queue(queue);
+        if let Some(unmapped) = unmapped.as_ref() {
+            copy = copy.ewait(unmapped);
+        }
+        copy.enq()?;
+        let mut ready = Event::empty();
+        range
+            .copy(&staging.range, None, None)
+            .queue(queue)
+            .enew(&mut ready)
+            .enq()?;
+        queue.flush()?;
+
+        let job = Job {
+            slot,
+            ready,
+            step,
+            time,
+        };
+        let sent = self.jobs.as_ref().map(|jobs| jobs.send(job).is_ok());
+        if sent != Some(true) {
+            return Err(self.encoder_error());
+        }
+        Ok(())
+    }
+
+    /// Waits for the remaining frames, then returns the path of the video.
+    pub fn finish(mut self) -> Result<String> {
+        std::mem::drop(self.jobs.take());
+        match self.encoder.take() {
+            Some(encoder) => encoder
+                .join()
+                .map_err(|_| anyhow!("Encoder thread panicked"))?,
+            None => Err(anyhow!("Encoder thread already stopped")),
+        }
+    }
+
+    fn encoder_error(&mut self) -> anyhow::Error {
+        std::mem::drop(self.jobs.take());
+        match self.encoder.take().map(|encoder| encoder.join()) {
+            Some(Ok(Err(e))) => e.context("Encoder thread failed"),
+            Some(Err(_)) => anyhow!("Encoder thread panicked"),
+            _ => anyhow!("Encoder thread stopped"),
+        }
+    }
+}
+
+/// A run stopped by an error, e.g. a blow-up, still encodes the frames already submitted and
+/// closes the video, which is then most needed.
+impl Drop for FramePipeline {
+    fn drop(&mut self) {
+        std::mem::drop(self.jobs.take());
+        match self.encoder.take().map(|encoder| encoder.join()) {
+            Some(Ok(Ok(path))) => println!("Video of the interrupted run written to {}", path),
+            Some(Ok(Err(e))) => println!("Warning : encoder thread failed : {:?}", e),
+            Some(Err(_)) => println!("Warning : encoder thread panicked"),
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use ocl::enums::ProfilingInfo;
+use ocl::{Event, Kernel, Queue};
+use std::fmt::Write;
+
+/// Pending spans are resolved once this many accumulate, so long runs keep a bounded list.
+const MAX_PENDING: usize = 4096;
+
+/// Device timings of the stages of a step, from OpenCL profiling events.
+///
+/// Needs a queue created with `QUEUE_PROFILING_ENABLE`. When disabled, kernels are enqueued as
+/// usual and nothing is recorded.
+pub struct Profiler {
+    enabled: bool,
+    // Stages in order of first appearance, with their durations in ns.
+    stages: Vec<(&'static str, Vec<u64>)>,
+    // Spans not resolved yet : stage, first event, last event.
+    pending: Vec<(&'static str, Event, Event)>,
+}
+
+/// Statistics of one stage, in milliseconds.
+pub struct StageReport {
+    pub name: &'static str,
+    pub count: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Fraction of the total device time of all the stages.
+    pub share: f64,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Profiler {
+        Profiler {
+            enabled,
+            stages: vec![],
+            pending: vec![],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enqueues `kernel` and records it under `stage`.
+    ///
+    /// # Safety
+    /// Same as `Kernel::enq`.
+    pub unsafe fn kernel(&mut self, stage: &'static str, kernel: &Kernel) -> Result<()> {
+        if !self.enabled {
+            kernel.enq()?;
+            return Ok(());
+        }
+        let mut event = Event::empty();
+        kernel.cmd().enew(&mut event).enq()?;
+        self.push(stage, event.clone(), event)
+    }
+
+    /// Marks the start of a span of commands on `queue`, to be closed by `end`.
+    /// Used for the commands that do not return an event, like `VkFFTAppend`.
+    pub fn start(&self, queue: &Queue) -> Result<Option<Event>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        Ok(Some(queue.enqueue_marker(None::<&Event>)?))
+    }
+
+    /// Records everything enqueued on `queue` since `start` under `stage`.
+    pub fn end(&mut self, stage: &'static str, queue: &Queue, start: Option<Event>) -> Result<()> {
+        if let Some(start) = start {
+            let end = queue.enqueue_marker(None::<&Event>)?;
+            self.push(stage, start, end)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, stage: &'static str, first: Event, last: Event) -> Result<()> {
+        self.pending.push((stage, first, last));
+        if self.pending.len() >= MAX_PENDING {
+            self.resolve()?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the pending spans and converts them to durations.
+    fn resolve(&mut self) -> Result<()> {
+        for (stage, first, last) in self.pending.drain(..) {
+            last.wait_for()?;
+            let start = first.profiling_info(ProfilingInfo::Start)?.time()?;
+            let end = last.profiling_info(ProfilingInfo::End)?.time()?;
+            let duration = end.saturating_sub(start);
+            match self.stages.iter_mut().find(|(name, _)| *name == stage) {
+                Some((_, durations)) => durations.push(duration),
+                None => self.stages.push((stage, vec![duration])),
+            }
+        }
+        Ok(())
+    }
+
+    /// Statistics of every stage recorded so far, waiting for the pending ones.
+    pub fn report(&mut self) -> Result<Vec<StageReport>> {
+        self.resolve()?;
+        let total: u64 = self.stages.iter().flat_map(|(_, d)| d.iter()).sum();
+        let ms = |ns: u64| ns as f64 * 1e-6;
+        Ok(self
+            .stages
+            .iter()
+            .map(|(name, durations)| {
+                let sum: u64 = durations.iter().sum();
+                StageReport {
+                    name,
+                    count: durations.len(),
+                    mean: ms(sum) / durations.len() as f64,
+                    min: ms(*durations.iter().min().unwrap_or(&0)),
+                    max: ms(*durations.iter().max().unwrap_or(&0)),
+                    share: if total > 0 {
+                        sum as f64 / total as f64
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect())
+    }
+}
+
+pub fn print_report(report: &[StageReport]) {
+    println!(
+        "{:<16} {:>6} {:>10} {:>10} {:>10} {:>7}",
+        "stage", "count", "mean (ms)", "min (ms)", "max (ms)", "share"
+    );
+    for s in report {
+        println!(
+            "{:<16} {:>6} {:>10.3} {:>10.3} {:>10.3} {:>6.1}%",
+            s.name,
+            s.count,
+            s.mean,
+            s.min,
+            s.max,
+            100.0 * s.share
+        );
+    }
+}
+
+/// Writes the report as a JSON array of stages, times in milliseconds.
+pub fn write_json(report: &[StageReport], path: &str) -> Result<()> {
+    let mut s = String::from("[\n");
+    for (k, stage) in report.iter().enumerate() {
+        write!(
+            s,
+            "  {{\"stage\": \"{}\", \"count\": {}, \"mean_ms\": {}, \"min_ms\": {}, \"max_ms\": {}, \"share\": {}}}",
+            stage.name, stage.count, stage.mean, stage.min, stage.max, stage.share
+        )?;
+        s.push_str(if k + 1 < report.len() { ",\n" } else { "\n" });
+    }
+    s.push_str("]\n");
+    std::fs::write(path, s)?;
+    Ok(())
+}
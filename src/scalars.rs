@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Result};
+use ndarray::Array2;
+use num::complex::Complex32;
+use ocl::Buffer;
+use ocl_vkfft::Plan;
+use std::f32::consts::PI;
+use std::fmt::Write;
+
+use crate::colormap::Colormap;
+use crate::free_slip::Boundary;
+use crate::utils::{self, get_from_gpu, mode_power, new_buffer};
+
+/// Initial condition of a passive scalar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Initial {
+    /// Fractal noise like the initial vorticity, with its own seed.
+    Noise(u32),
+    /// 1 on `count` bands along i, 0 between them.
+    Stripes(usize),
+    /// Gaussian of standard deviation `width` times the side of the box, at its center.
+    Blob(f32),
+}
+
+impl Initial {
+    pub fn field(&self, n: usize) -> Array2<Complex32> {
+        match *self {
+            Initial::Noise(seed) => utils::noise2d_seeded(n, seed),
+            Initial::Stripes(count) => Array2::from_shape_fn((n, n), |(i, _)| {
+                Complex32::new((2 * count * i / n).is_multiple_of(2) as i32 as f32, 0.0)
+            }),
+            Initial::Blob(width) => {
+                let sigma = width * n as f32;
+                Array2::from_shape_fn((n, n), |(i, j)| {
+                    let di = i as f32 - (n / 2) as f32;
+                    let dj = j as f32 - (n / 2) as f32;
+                    let r2 = (di * di + dj * dj) / (sigma * sigma);
+                    Complex32::new((-0.5 * r2).exp(), 0.0)
+                })
+            }
+        }
+    }
+}
+
+/// A field such as dye or temperature carried by the flow without acting on it.
+#[derive(Clone, Copy, Debug)]
+pub struct ScalarConfig {
+    /// Used in file names and annotations.
+    pub name: &'static str,
+    /// κ of ∂_t c + u·∇c = κΔc, the diffusion is exact in spectral space and needs a periodic box.
+    pub diffusivity: f32,
+    pub initial: Initial,
+    pub colormap: Colormap,
+}
+
+/// Mean, variance and isotropic spectrum of a scalar, from its Fourier transform.
+#[derive(Clone, Debug)]
+pub struct Statistics {
+    pub mean: f32,
+    /// ⟨c²⟩ - ⟨c⟩², which only diffusion and the interpolation of the advection decrease.
+    pub variance: f32,
+    /// ½ Σ|ĉ|² over the shells k - ½ ≤ |k| L / 2π < k + ½, summing to ½⟨c²⟩.
+    pub spectrum: Vec<f32>,
+}
+
+struct Scalar {
+    config: ScalarConfig,
+    c: Buffer<Complex32>,
+    cnew: Buffer<Complex32>,
+    kernel_advection: ocl::Kernel,
+    kernel_diffusion: ocl::Kernel,
+    history: Vec<(u64, f32, Statistics)>,
+}
+
+/// Passive scalars advected by the velocity of the vorticity, with the same kernels.
+pub struct PassiveScalars {
+    n: usize,
+    hat: Buffer<Complex32>,
+    scalars: Vec<Scalar>,
+}
+
+impl PassiveScalars {
+    /// Reads the velocity in the real parts of `ux` and `uy`, which must be up to date when
+    /// `step` is enqueued.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        program: &ocl::Program,
+        queue: &ocl::Queue,
+        n: usize,
+        l: f32,
+        dt: f32,
+        boundary: Boundary,
+        configs: &[ScalarConfig],
+        ux: &Buffer<Complex32>,
+        uy: &Buffer<Complex32>,
+    ) -> Result<PassiveScalars> {
+        let hat = new_buffer(queue, n)?;
+        let mut scalars = Vec::new();
+        for config in configs {
+            if config.diffusivity > 0.0 && boundary != Boundary::Periodic {
+                return Err(anyhow!(
+                    "Diffusion of the scalar {} needs a periodic box",
+                    config.name
+                ));
+            }
+            let c = new_buffer(queue, n)?;
+            let cnew = new_buffer(queue, n)?;
+            let init = config.initial.field(n);
+            c.write(init.as_slice().ok_or(anyhow!("Non contiguous scalar"))?)
+                .enq()?;
+            let kernel_advection = unsafe {
+                ocl::Kernel::builder()
+                    .program(program)
+                    .queue(queue.clone())
+                    .name(match boundary {
+                        Boundary::Periodic => "advection",
+                        Boundary::FreeSlip => "advection_walls",
+                    })
+                    .global_work_size([n, n])
+                    .disable_arg_type_check()
+                    .arg(&c)
+                    .arg(&cnew)
+                    .arg(ux)
+                    .arg(uy)
+                    .arg(n as i32)
+                    .arg(l)
+                    .arg(dt)
+                    .build()?
+            };
+            let kernel_diffusion = unsafe {
+                ocl::Kernel::builder()
+                    .program(program)
+                    .queue(queue.clone())
+                    .name("diffusion_hat")
+                    .global_work_size([n, n])
+                    .disable_arg_type_check()
+                    .arg(&hat)
+                    .arg(n as i32)
+                    .arg(2.0 * PI / l)
+                    .arg(config.diffusivity * dt)
+                    .build()?
+            };
+            scalars.push(Scalar {
+                config: *config,
+                c,
+                cnew,
+                kernel_advection,
+                kernel_diffusion,
+                history: Vec::new(),
+            });
+        }
+        Ok(PassiveScalars { n, hat, scalars })
+    }
+
+    /// Name and buffer of every scalar, rendered as `Field::Scalar` by the field renderer.
+    pub fn fields(&self) -> Vec<(&'static str, Buffer<Complex32>)> {
+        self.scalars
+            .iter()
+            .map(|scalar| (scalar.config.name, scalar.c.clone()))
+            .collect()
+    }
+
+    /// Enqueues the advection of every scalar by one step, then its diffusion.
+    pub fn step(&self, queue: &ocl::Queue, fft: &mut Plan) -> Result<()> {
+        for scalar in &self.scalars {
+            unsafe {
+                scalar.kernel_advection.enq()?;
+            }
+            if scalar.config.diffusivity > 0.0 {
+                fft.forward(queue, &scalar.cnew, &self.hat)?;
+                unsafe {
+                    scalar.kernel_diffusion.enq()?;
+                }
+                self.hat.copy(&scalar.c, None, None).enq()?;
+                fft.inverse(queue, &scalar.c)?;
+            } else {
+                scalar.cnew.copy(&scalar.c, None, None).enq()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Measures the statistics of every scalar and keeps them for `write_history`. Blocks until
+    /// the queue is done.
+    pub fn measure(
+        &mut self,
+        queue: &ocl::Queue,
+        fft: &mut Plan,
+        step: u64,
+        time: f32,
+    ) -> Result<()> {
+        let nn = (self.n * self.n) as f32;
+        let half = (self.n / 2) as i64;
+        for scalar in &mut self.scalars {
+            fft.forward(queue, &scalar.c, &self.hat)?;
+            let hat = get_from_gpu(&self.hat)?;
+            let mut spectrum = vec![0f32; (half as f32 * 2f32.sqrt()).ceil() as usize + 1];
+            for ((i, j), power) in mode_power(&hat).indexed_iter() {
+                let ki = i as i64 - self.n as i64 * (i as i64 >= half) as i64;
+                let kj = j as i64 - self.n as i64 * (j as i64 >= half) as i64;
+                let k = ((ki * ki + kj * kj) as f32).sqrt().round() as usize;
+                spectrum[k] += 0.5 * power;
+            }
+            let mean = hat[[0, 0]].re / nn;
+            let variance = 2.0 * spectrum.iter().sum::<f32>() - mean * mean;
+            scalar.history.push((
+                step,
+                time,
+                Statistics {
+                    mean,
+                    variance,
+                    spectrum,
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the measured mean and variance of each scalar to `<dir>/<name>_variance.csv`, and
+    /// its spectra to `<dir>/<name>_spectrum.csv`.
+    pub fn write_history(&self, dir: &str) -> Result<()> {
+        for scalar in &self.scalars {
+            let mut variance = String::from("step,time,mean,variance\n");
+            let mut spectrum = String::from("step,k,energy\n");
+            for (step, time, stats) in &scalar.history {
+                writeln!(
+                    variance,
+                    "{},{},{},{}",
+                    step, time, stats.mean, stats.variance
+                )?;
+                for (k, e) in stats.spectrum.iter().enumerate() {
+                    writeln!(spectrum, "{},{},{}", step, k, e)?;
+                }
+            }
+            let name = scalar.config.name;
+            std::fs::write(format!("{}/{}_variance.csv", dir, name), variance)?;
+            std::fs::write(format!("{}/{}_spectrum.csv", dir, name), spectrum)?;
+            if let (Some(first), Some(last)) = (scalar.history.first(), scalar.history.last()) {
+                println!(
+                    "Scalar {} : variance {:e} at step {}, {:e} at step {}",
+                    name, first.2.variance, first.0, last.2.variance, last.0
+                );
+            }
+        }
+        Ok(())
+    }
+}
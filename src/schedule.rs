@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+
+/// When an output is produced during the run.
+#[derive(Clone, Copy, Debug)]
+pub enum Schedule {
+    Never,
+    /// Every k steps, starting at step 0. k must be positive.
+    EverySteps(u64),
+    /// Every Δt of simulated time, starting at t = 0. Δt must be positive.
+    EveryTime(f32),
+    /// At the listed times, in increasing order.
+    At(&'static [f32]),
+}
+
+/// Tracks which outputs of a schedule were already produced.
+pub struct Cadence {
+    schedule: Schedule,
+    // Index of the next target time, k Δt for `EveryTime`.
+    next_index: usize,
+}
+
+impl Cadence {
+    pub fn new(schedule: Schedule) -> Result<Cadence> {
+        match schedule {
+            Schedule::EverySteps(0) => return Err(anyhow!("Output every 0 steps")),
+            Schedule::EveryTime(interval) if !(interval > 0.0 && interval.is_finite()) => {
+                return Err(anyhow!(
+                    "Output interval must be positive, not {}",
+                    interval
+                ));
+            }
+            _ => {}
+        }
+        Ok(Cadence {
+            schedule,
+            next_index: 0,
+        })
+    }
+
+    /// Whether the output is due at `step`, of time `time`. Times are matched to the nearest step,
+    /// and several targets falling within one step only produce one output.
+    pub fn due(&mut self, step: u64, time: f32, dt: f32) -> bool {
+        let reached = |target: f32| time + 0.5 * dt >= target;
+        match self.schedule {
+            Schedule::Never => false,
+            Schedule::EverySteps(k) => step.is_multiple_of(k),
+            Schedule::EveryTime(interval) => {
+                // Targets computed from their index in f64, so that they keep increasing when
+                // the interval is below the resolution of the time.
+                let now = time as f64 + 0.5 * dt as f64;
+                let interval = interval as f64;
+                if now < self.next_index as f64 * interval {
+                    return false;
+                }
+                self.next_index = (now / interval).floor() as usize + 1;
+                true
+            }
+            Schedule::At(times) => {
+                let before = self.next_index;
+                while self.next_index < times.len() && reached(times[self.next_index]) {
+                    self.next_index += 1;
+                }
+                self.next_index > before
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steps of `0..steps` at which `schedule` is due, with time step `dt`.
+    fn due_steps(schedule: Schedule, steps: u64, dt: f32) -> Vec<u64> {
+        let mut cadence = Cadence::new(schedule).unwrap();
+        (0..steps)
+            .filter(|&step| cadence.due(step, step as f32 * dt, dt))
+            .collect()
+    }
+
+    #[test]
+    fn invalid_schedules() {
+        assert!(Cadence::new(Schedule::EverySteps(0)).is_err());
+        for interval in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(Cadence::new(Schedule::EveryTime(interval)).is_err());
+        }
+    }
+
+    #[test]
+    fn every_steps() {
+        assert_eq!(due_steps(Schedule::EverySteps(3), 10, 0.1), [0, 3, 6, 9]);
+        assert_eq!(due_steps(Schedule::EverySteps(1), 3, 0.1), [0, 1, 2]);
+        assert!(due_steps(Schedule::Never, 10, 0.1).is_empty());
+    }
+
+    #[test]
+    fn every_time() {
+        assert_eq!(due_steps(Schedule::EveryTime(0.3), 10, 0.1), [0, 3, 6, 9]);
+        // An interval of one step, or below it, outputs once every step.
+        assert_eq!(due_steps(Schedule::EveryTime(0.1), 10000, 0.1).len(), 10000);
+        assert_eq!(due_steps(Schedule::EveryTime(0.01), 100, 0.1).len(), 100);
+    }
+
+    #[test]
+    fn every_time_below_time_resolution() {
+        // Around 1e5 the spacing of f32 is 8e-3, far above the interval.
+        let mut cadence = Cadence::new(Schedule::EveryTime(1e-3)).unwrap();
+        let time = 1e5f32;
+        assert!(cadence.due(0, time, 1e-2));
+        assert!(!cadence.due(1, time, 1e-2));
+        assert!(cadence.due(2, time + 1.0, 1e-2));
+        assert!(!cadence.due(3, time + 1.0, 1e-2));
+    }
+
+    #[test]
+    fn listed_times() {
+        // 0.32 and 0.34 fall within step 3, which outputs once.
+        let times = &[0.0, 0.32, 0.34, 1.0];
+        assert_eq!(due_steps(Schedule::At(times), 20, 0.1), [0, 3, 10]);
+        assert!(due_steps(Schedule::At(&[]), 20, 0.1).is_empty());
+    }
+}
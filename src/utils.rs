@@ -1,7 +1,6 @@
 extern crate noise;
 
 use anyhow::{anyhow, Result};
-use colorgrad::Gradient;
 use core::f64;
 use image::ImageBuffer;
 use ndarray::Array2;
@@ -13,11 +12,18 @@ use ocl::Buffer;
 use plotters::prelude::*;
 use std::f32::consts::PI;
 
+use crate::colormap::ColorScale;
+
 pub fn noise2d(n: usize) -> Array2<Complex32> {
+    noise2d_seeded(n, 12)
+}
+
+/// Periodic fractal noise in the real part, different for each `seed`.
+pub fn noise2d_seeded(n: usize, seed: u32) -> Array2<Complex32> {
     let s = 2.0 * f64::consts::PI / (n as f64);
     let r = 10.0;
     let mut a = Array2::<Complex32>::zeros((n, n));
-    let perlin: Fbm<Perlin> = Fbm::new(12);
+    let perlin: Fbm<Perlin> = Fbm::new(seed);
     for i in 0..n {
         for j in 0..n {
             a[[i, j]].re = perlin.get([
@@ -87,6 +93,13 @@ pub fn fftfreq(n: usize, l: f32) -> Vec<f32> {
     return freq;
 }
 
+/// Power of each mode of the unnormalized forward transform `hat` of a field, by Parseval
+/// ⟨|f|²⟩ = Σ|f̂|² / N⁴ : the powers sum to the mean square of the field.
+pub fn mode_power(hat: &Array2<Complex32>) -> Array2<f32> {
+    let nn = hat.len() as f32;
+    hat.mapv(|x| x.norm_sqr() / (nn * nn))
+}
+
 pub fn get_from_gpu(buffer: &Buffer<Complex<f32>>) -> Result<Array2<Complex<f32>>> {
     let n = buffer.len().sqrt();
     let mut cpu_data = Array2::<Complex<f32>>::zeros((n, n));
@@ -107,29 +120,22 @@ pub fn printmax(buffer: &Buffer<Complex<f32>>, name: &str) -> Result<()> {
     return Ok(());
 }
 
-pub fn image_from_array(cpu_data: &Array2<f32>) -> Result<ImageBuffer<image::Rgb<u8>, Vec<u8>>> {
-    let n = cpu_data.len_of(ndarray::Axis(0));
-    let m = max(&cpu_data.mapv(f32::abs));
-    let normalized = cpu_data.mapv(|x| 0.5 + x / (2.0 * m));
-    let grad = colorgrad::GradientBuilder::new()
-        .html_colors(&["red", "white", "blue"])
-        .build::<colorgrad::CatmullRomGradient>()?;
-    let imgbuf = image::ImageBuffer::from_fn(n as u32, n as u32, |i, j| {
-        let v = grad.at(normalized[[i as usize, j as usize]]).to_rgba8();
-        image::Rgb(v[..3].try_into().unwrap())
-    });
-    return Ok(imgbuf);
+pub fn image_from_array(
+    cpu_data: &Array2<f32>,
+    scale: &mut ColorScale,
+) -> Result<ImageBuffer<image::Rgb<u8>, Vec<u8>>> {
+    return Ok(scale.image(cpu_data));
 }
 
-pub fn plot_array(cpu_data: &Array2<f32>, name: &str) -> Result<()> {
-    let imgbuf = image_from_array(cpu_data)?;
+pub fn plot_array(cpu_data: &Array2<f32>, name: &str, scale: &mut ColorScale) -> Result<()> {
+    let imgbuf = image_from_array(cpu_data, scale)?;
     imgbuf.save(name)?;
     return Ok(());
 }
 
-pub fn plot_from_gpu(buffer: &Buffer<Complex32>, name: &str) -> Result<()> {
+pub fn plot_from_gpu(buffer: &Buffer<Complex32>, name: &str, scale: &mut ColorScale) -> Result<()> {
     let cpu_data = get_from_gpu(&buffer)?.mapv(|x| x.re);
-    plot_array(&cpu_data, name)?;
+    plot_array(&cpu_data, name, scale)?;
     return Ok(());
 }
 
@@ -140,3 +146,15 @@ pub fn new_buffer(queue: &ocl::Queue, n: usize) -> Result<Buffer<Complex32>> {
         .build()?;
     return Ok(buffer);
 }
+
+/// Writes the buffer to `name` as raw little-endian (re, im) f32 pairs, row-major.
+pub fn save_raw(buffer: &Buffer<Complex32>, name: &str) -> Result<()> {
+    let cpu_data = get_from_gpu(buffer)?;
+    let mut bytes = Vec::with_capacity(8 * cpu_data.len());
+    for x in cpu_data.iter() {
+        bytes.extend_from_slice(&x.re.to_le_bytes());
+        bytes.extend_from_slice(&x.im.to_le_bytes());
+    }
+    std::fs::write(name, bytes)?;
+    return Ok(());
+}
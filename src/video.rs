@@ -0,0 +1,270 @@
+use anyhow::{anyhow, Result};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, ImageBuffer, Rgb};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug)]
+pub struct VideoConfig {
+    pub format: VideoFormat,
+    /// Output file, `videos/<unix time>.<extension>` if `None`.
+    pub path: Option<&'static str>,
+    pub fps: u32,
+    /// Side of the encoded frames, the field is box-averaged on the device down to it.
+    /// Must divide the grid size, `None` keeps the full resolution.
+    pub resolution: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum VideoFormat {
+    /// Any codec through an external ffmpeg process.
+    Ffmpeg(FfmpegOptions),
+    /// Animated GIF encoded in process, large and slow but plays everywhere.
+    Gif,
+    /// Uncompressed YUV4MPEG2 stream written in process, any player or encoder reads it.
+    Y4m,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FfmpegOptions {
+    /// Encoder name as given to `-c:v`, e.g. "libx264", "libx265", "libvpx-vp9".
+    pub codec: &'static str,
+    /// Constant rate factor, lower is better quality.
+    pub crf: u32,
+    /// Output pixel format as given to `-pix_fmt`, e.g. "yuv420p" for maximal player support.
+    pub pixel_format: &'static str,
+    pub threads: u32,
+}
+
+impl VideoFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoFormat::Ffmpeg(options) if options.codec.starts_with("libvpx") => "webm",
+            VideoFormat::Ffmpeg(_) => "mp4",
+            VideoFormat::Gif => "gif",
+            VideoFormat::Y4m => "y4m",
+        }
+    }
+}
+
+impl VideoConfig {
+    /// Side of the encoded frames for a grid of side `n`, checked against the encoder constraints.
+    pub fn frame_size(&self, n: usize) -> Result<usize> {
+        let size = self.resolution.unwrap_or(n);
+        if size == 0 || size > n || !n.is_multiple_of(size) {
+            return Err(anyhow!(
+                "Video resolution {} must divide the grid size {}",
+                size,
+                n
+            ));
+        }
+        if let VideoFormat::Ffmpeg(options) = self.format {
+            // Chroma subsampled formats store one chroma sample per 2 pixels along each subsampled axis.
+            let subsampled = ["420", "422", "411", "nv12", "nv21"]
+                .iter()
+                .any(|p| options.pixel_format.contains(p));
+            if subsampled && !size.is_multiple_of(2) {
+                return Err(anyhow!(
+                    "Pixel format {} needs even frame dimensions, got {}x{}",
+                    options.pixel_format,
+                    size,
+                    size
+                ));
+            }
+        }
+        Ok(size)
+    }
+}
+
+pub enum VideoWriter {
+    Ffmpeg {
+        path: String,
+        child: Child,
+        stdin: ChildStdin,
+        stderr: Option<JoinHandle<String>>,
+    },
+    Gif {
+        path: String,
+        encoder: GifEncoder<BufWriter<File>>,
+        fps: u32,
+    },
+    Y4m {
+        path: String,
+        out: BufWriter<File>,
+    },
+}
+
+/// Fails early, with a useful message, if ffmpeg cannot be run.
+pub fn check_ffmpeg() -> Result<()> {
+    match Command::new("ffmpeg").arg("-version").output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(anyhow!(
+            "ffmpeg -version failed : {}",
+            String::from_utf8_lossy(&output.stderr)
+        )),
+        Err(e) => Err(anyhow!(
+            "Cannot run ffmpeg ({}), install it or use VideoFormat::Gif or VideoFormat::Y4m",
+            e
+        )),
+    }
+}
+
+impl VideoWriter {
+    /// Opens the output file, creating its directory if needed, for square frames of side `size`.
+    pub fn new(config: &VideoConfig, size: usize) -> Result<VideoWriter> {
+        let path = match config.path {
+            Some(path) => path.to_string(),
+            None => {
+                let sys_time = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_secs();
+                format!("videos/{}.{}", sys_time, config.format.extension())
+            }
+        };
+        if let Some(dir) = Path::new(&path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let fps = config.fps;
+        match config.format {
+            VideoFormat::Ffmpeg(options) => {
+                check_ffmpeg()?;
+                let mut child = Command::new("ffmpeg")
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .args([
+                        "-hide_banner",
+                        "-loglevel",
+                        "warning",
+                        "-f",
+                        "rawvideo",
+                        "-pixel_format",
+                        "rgb24",
+                        "-video_size",
+                        &format!("{}x{}", size, size),
+                        "-framerate",
+                        &fps.to_string(),
+                        "-i",
+                        "pipe:",
+                        "-c:v",
+                        options.codec,
+                        "-pix_fmt",
+                        options.pixel_format,
+                        "-threads",
+                        &options.threads.to_string(),
+                        "-crf",
+                        &options.crf.to_string(),
+                        "-y",
+                        &path,
+                    ])
+                    .spawn()?;
+                let stdin = child.stdin.take().ok_or(anyhow!("No ffmpeg stdin"))?;
+                // Drained on its own thread so that a chatty ffmpeg never blocks on a full pipe.
+                let mut pipe = child.stderr.take().ok_or(anyhow!("No ffmpeg stderr"))?;
+                let stderr = std::thread::spawn(move || {
+                    let mut s = String::new();
+                    let _ = pipe.read_to_string(&mut s);
+                    s
+                });
+                Ok(VideoWriter::Ffmpeg {
+                    path,
+                    child,
+                    stdin,
+                    stderr: Some(stderr),
+                })
+            }
+            VideoFormat::Gif => {
+                let mut encoder = GifEncoder::new(BufWriter::new(File::create(&path)?));
+                encoder.set_repeat(Repeat::Infinite)?;
+                Ok(VideoWriter::Gif { path, encoder, fps })
+            }
+            VideoFormat::Y4m => {
+                let mut out = BufWriter::new(File::create(&path)?);
+                writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", size, size, fps)?;
+                Ok(VideoWriter::Y4m { path, out })
+            }
+        }
+    }
+
+    pub fn write_frame(&mut self, img: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<()> {
+        match self {
+            VideoWriter::Ffmpeg {
+                child,
+                stdin,
+                stderr,
+                ..
+            } => {
+                if let Err(e) = stdin.write_all(img.as_raw()) {
+                    // ffmpeg exited, its stderr says why.
+                    let status = child.wait()?;
+                    let stderr = stderr.take().and_then(|h| h.join().ok());
+                    return Err(anyhow!(
+                        "Writing to ffmpeg failed ({}), ffmpeg exited with {} : {}",
+                        e,
+                        status,
+                        stderr.unwrap_or_default()
+                    ));
+                }
+            }
+            VideoWriter::Gif { encoder, fps, .. } => {
+                let rgba = DynamicImage::ImageRgb8(img.clone()).into_rgba8();
+                let delay = Delay::from_numer_denom_ms(1000, *fps);
+                encoder.encode_frame(Frame::from_parts(rgba, 0, 0, delay))?;
+            }
+            VideoWriter::Y4m { out, .. } => {
+                // BT.601 studio swing, planar 4:4:4.
+                let len = img.len() / 3;
+                let mut planes = [0; 3].map(|_| Vec::with_capacity(len));
+                for p in img.pixels() {
+                    let [r, g, b] = p.0.map(|c| c as f32 / 255.0);
+                    let y = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+                    let u = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+                    let v = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+                    planes[0].push(y.round() as u8);
+                    planes[1].push(u.round() as u8);
+                    planes[2].push(v.round() as u8);
+                }
+                out.write_all(b"FRAME\n")?;
+                for plane in planes {
+                    out.write_all(&plane)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the video and returns its path, or the ffmpeg error output.
+    pub fn finish(self) -> Result<String> {
+        match self {
+            VideoWriter::Ffmpeg {
+                path,
+                mut child,
+                stdin,
+                stderr,
+            } => {
+                std::mem::drop(stdin);
+                let status = child.wait()?;
+                let stderr = stderr.and_then(|h| h.join().ok()).unwrap_or_default();
+                if !status.success() {
+                    return Err(anyhow!("ffmpeg failed ({}) : {}", status, stderr));
+                }
+                if !stderr.is_empty() {
+                    println!("ffmpeg : {}", stderr);
+                }
+                Ok(path)
+            }
+            VideoWriter::Gif { path, encoder, .. } => {
+                std::mem::drop(encoder);
+                Ok(path)
+            }
+            VideoWriter::Y4m { path, mut out } => {
+                out.flush()?;
+                Ok(path)
+            }
+        }
+    }
+}